@@ -0,0 +1,488 @@
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs};
+
+// Programs are loaded at 0x200, so labels are addresses from there on.
+const PROGRAM_START: usize = 0x200;
+// Constants may be defined in terms of other constants and files may include
+// other files, but not endlessly.
+const MAX_DEPTH: usize = 32;
+
+/// An error in the assembly source, with the file and line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl error::Error for AsmError {}
+
+enum Kind {
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+struct Statement {
+    file: String,
+    line: usize,
+    kind: Kind,
+}
+
+// The program as read in the first pass: every statement with its symbols.
+#[derive(Default)]
+struct Program {
+    statements: Vec<Statement>,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, String>,
+    addr: usize,
+}
+
+/// Assembles `source` into a ROM that can be loaded with `C8::load_ram`.
+///
+/// The source uses the mnemonics the disassembler prints (Cowgod's, with the
+/// SUPER-CHIP and XO-CHIP additions), one statement per line:
+///
+/// ```text
+/// ; Comments start with a semicolon
+/// SPEED equ 2             ; constants
+/// start:                  ; labels, for the address of the next statement
+///     LD I, sprite
+///     LD V0, SPEED
+///     DRW V0, V1, 5
+///     JP start
+/// sprite:
+///     db 0xF0, 0x90, 0x90 ; data bytes, or 16-bit words with dw
+/// include "font.asm"      ; inserts another source file
+/// ```
+///
+/// Includes are resolved relative to the current directory, see
+/// `assemble_file` to resolve them relative to the including file.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut program = Program {
+        addr: PROGRAM_START,
+        ..Program::default()
+    };
+    program.read("<source>", source, Path::new("."), 0)?;
+    program.encode()
+}
+
+/// Reads and assembles the source file at `path`.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut program = Program {
+        addr: PROGRAM_START,
+        ..Program::default()
+    };
+    program.include(path, "<command line>", 0, 0)?;
+    program.encode()
+}
+
+impl Program {
+    fn include(
+        &mut self,
+        path: &Path,
+        from: &str,
+        line: usize,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let error = |message: String| AsmError {
+            file: from.to_string(),
+            line,
+            message,
+        };
+        if depth > MAX_DEPTH {
+            return Err(error(format!(
+                "Includes nested too deep at {}",
+                path.display()
+            )));
+        }
+
+        let source = fs::read_to_string(path)
+            .map_err(|e| error(format!("Couldn't read {}: {}", path.display(), e)))?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.read(&path.display().to_string(), &source, &dir, depth)
+    }
+
+    // First pass: collects statements, labels and constants, and works out
+    // the address of every statement.
+    fn read(&mut self, file: &str, source: &str, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        for (j, text) in source.lines().enumerate() {
+            let line = j + 1;
+            let error = |message: String| AsmError {
+                file: file.to_string(),
+                line,
+                message,
+            };
+
+            let mut text = strip_comment(text).trim();
+
+            // Any number of labels can come before the statement.
+            while let Some(colon) = text.find(':') {
+                let label = text[..colon].trim();
+                if !is_symbol(label) {
+                    break;
+                }
+                if self.labels.insert(label.to_string(), self.addr).is_some() {
+                    return Err(error(format!("Label {} defined twice", label)));
+                }
+                text = text[colon + 1..].trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = split_word(text);
+            let (next, rest_rest) = split_word(rest);
+            if next.eq_ignore_ascii_case("equ") {
+                if !is_symbol(word) {
+                    return Err(error(format!("Invalid constant name {}", word)));
+                }
+                self.constants
+                    .insert(word.to_string(), rest_rest.to_string());
+                continue;
+            }
+
+            let operands = split_operands(rest);
+            let kind = match word.to_lowercase().as_str() {
+                "include" => {
+                    let name = rest.trim_matches('"');
+                    let path: PathBuf = dir.join(name);
+                    self.include(&path, file, line, depth + 1)?;
+                    continue;
+                }
+                "db" => Kind::Bytes(operands),
+                "dw" => Kind::Words(operands),
+                _ => Kind::Instruction(word.to_uppercase(), operands),
+            };
+
+            self.addr += match &kind {
+                Kind::Bytes(values) => values.len(),
+                Kind::Words(values) => values.len() * 2,
+                Kind::Instruction(mnemonic, operands) => {
+                    let long = operands
+                        .get(1)
+                        .is_some_and(|o| o.eq_ignore_ascii_case("long"));
+                    if mnemonic == "LD" && long {
+                        4
+                    } else {
+                        2
+                    }
+                }
+            };
+            self.statements.push(Statement {
+                file: file.to_string(),
+                line,
+                kind,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Second pass: now that every label is known, encodes the statements.
+    fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for statement in self.statements.iter() {
+            let error = |message: String| AsmError {
+                file: statement.file.clone(),
+                line: statement.line,
+                message,
+            };
+
+            match &statement.kind {
+                Kind::Bytes(values) => {
+                    for value in values {
+                        rom.push(self.number(value, 0xFF).map_err(error)? as u8);
+                    }
+                }
+                Kind::Words(values) => {
+                    for value in values {
+                        let word = self.number(value, 0xFFFF).map_err(error)?;
+                        rom.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+                    }
+                }
+                Kind::Instruction(mnemonic, operands) => {
+                    for word in self.instruction(mnemonic, operands).map_err(error)? {
+                        rom.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+                    }
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+
+    // Encodes one instruction into its opcode, or two 16-bit words for
+    // `LD I, LONG addr`.
+    fn instruction(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<u16>, String> {
+        use Instruction::*;
+
+        let ops: Vec<&str> = operands.iter().map(|o| o.as_str()).collect();
+        let upper: Vec<String> = operands.iter().map(|o| o.to_uppercase()).collect();
+        let upper: Vec<&str> = upper.iter().map(|o| o.as_str()).collect();
+
+        let addr = |text: &str| self.number(text, 0xFFF);
+        let byte = |text: &str| self.number(text, 0xFF).map(|kk| kk as u8);
+        let nibble = |text: &str| self.number(text, 0xF).map(|n| n as u8);
+
+        let instruction = match (mnemonic, upper.as_slice()) {
+            ("CLS", []) => Cls,
+            ("RET", []) => Ret,
+            ("SCD", [_]) => ScrollDown(nibble(ops[0])?),
+            ("SCU", [_]) => ScrollUp(nibble(ops[0])?),
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Low,
+            ("HIGH", []) => High,
+            ("SYS", [_]) => Sys(addr(ops[0])?),
+            ("JP", [_]) => Jump(addr(ops[0])?),
+            ("JP", [v, _]) => {
+                // BNNN jumps to NNN + V0. As BXNN (jump_uses_vx) the register
+                // is the highest nibble of the address.
+                let x = register(v)?;
+                let nnn = addr(ops[1])?;
+                if x != 0 && (nnn >> 8) as usize != x {
+                    return Err(format!(
+                        "JP V{:X} needs an address in 0x{:X}00-0x{:X}FF",
+                        x, x, x
+                    ));
+                }
+                JumpOffset(nnn)
+            }
+            ("CALL", [_]) => Call(addr(ops[0])?),
+            ("SE", [vx, vy]) if is_register(vy) => SkipEqReg(register(vx)?, register(vy)?),
+            ("SE", [vx, _]) => SkipEqByte(register(vx)?, byte(ops[1])?),
+            ("SNE", [vx, vy]) if is_register(vy) => SkipNeReg(register(vx)?, register(vy)?),
+            ("SNE", [vx, _]) => SkipNeByte(register(vx)?, byte(ops[1])?),
+            ("LD", ["I", "LONG", _]) => {
+                return Ok(vec![LoadILong.encode(), self.number(ops[2], 0xFFFF)?])
+            }
+            ("LD", ["I", _]) => LoadI(addr(ops[1])?),
+            ("LD", ["DT", vx]) => SetDelay(register(vx)?),
+            ("LD", ["ST", vx]) => SetSound(register(vx)?),
+            ("LD", ["F", vx]) => LoadFont(register(vx)?),
+            ("LD", ["HF", vx]) => LoadBigFont(register(vx)?),
+            ("LD", ["B", vx]) => Bcd(register(vx)?),
+            ("LD", ["R", vx]) => StoreFlags(register(vx)?),
+            ("LD", ["[I]", range]) if range.contains('-') => {
+                let (x, y) = register_range(range)?;
+                StoreRange(x, y)
+            }
+            ("LD", ["[I]", vx]) => StoreRegs(register(vx)?),
+            ("LD", [range, "[I]"]) if range.contains('-') => {
+                let (x, y) = register_range(range)?;
+                LoadRange(x, y)
+            }
+            ("LD", [vx, "[I]"]) => LoadRegs(register(vx)?),
+            ("LD", [vx, "DT"]) => LoadDelay(register(vx)?),
+            ("LD", [vx, "K"]) => WaitKey(register(vx)?),
+            ("LD", [vx, "R"]) => LoadFlags(register(vx)?),
+            ("LD", [vx, vy]) if is_register(vy) => LoadReg(register(vx)?, register(vy)?),
+            ("LD", [vx, _]) => LoadByte(register(vx)?, byte(ops[1])?),
+            ("ADD", ["I", vx]) => AddI(register(vx)?),
+            ("ADD", [vx, vy]) if is_register(vy) => AddReg(register(vx)?, register(vy)?),
+            ("ADD", [vx, _]) => AddByte(register(vx)?, byte(ops[1])?),
+            ("OR", [vx, vy]) => Or(register(vx)?, register(vy)?),
+            ("AND", [vx, vy]) => And(register(vx)?, register(vy)?),
+            ("XOR", [vx, vy]) => Xor(register(vx)?, register(vy)?),
+            ("SUB", [vx, vy]) => Sub(register(vx)?, register(vy)?),
+            ("SHR", [vx]) => ShiftRight(register(vx)?, 0),
+            ("SHR", [vx, vy]) => ShiftRight(register(vx)?, register(vy)?),
+            ("SUBN", [vx, vy]) => SubN(register(vx)?, register(vy)?),
+            ("SHL", [vx]) => ShiftLeft(register(vx)?, 0),
+            ("SHL", [vx, vy]) => ShiftLeft(register(vx)?, register(vy)?),
+            ("RND", [vx, _]) => Random(register(vx)?, byte(ops[1])?),
+            ("DRW", [vx, vy, _]) => Draw(register(vx)?, register(vy)?, nibble(ops[2])?),
+            ("SKP", [vx]) => SkipKey(register(vx)?),
+            ("SKNP", [vx]) => SkipNotKey(register(vx)?),
+            ("PLANE", [_]) => Plane(nibble(ops[0])?),
+            ("AUDIO", []) => Audio,
+            ("PITCH", [vx]) => Pitch(register(vx)?),
+            _ => {
+                return Err(format!(
+                    "Unknown instruction: {} {}",
+                    mnemonic,
+                    operands.join(", ")
+                ))
+            }
+        };
+
+        Ok(vec![instruction.encode()])
+    }
+
+    // Evaluates a number, label or constant and checks that it fits in `max`.
+    fn number(&self, text: &str, max: u32) -> Result<u16, String> {
+        let value = self.value(text, 0)?;
+        if value > max {
+            return Err(format!("{} does not fit in {:#X}", text, max));
+        }
+        Ok(value as u16)
+    }
+
+    fn value(&self, text: &str, depth: usize) -> Result<u32, String> {
+        let text = text.trim();
+        if let Some(addr) = self.labels.get(text) {
+            return Ok(*addr as u32);
+        }
+        if let Some(expr) = self.constants.get(text) {
+            if depth == MAX_DEPTH {
+                return Err(format!("Constant {} refers to itself", text));
+            }
+            return self.value(expr, depth + 1);
+        }
+
+        let lower = text.to_lowercase();
+        let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+            u32::from_str_radix(hex, 16)
+        } else if let Some(bin) = lower.strip_prefix("0b") {
+            u32::from_str_radix(bin, 2)
+        } else {
+            lower.parse::<u32>()
+        };
+        parsed.map_err(|_| format!("Unknown value {}", text))
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (j, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..j],
+            _ => (),
+        }
+    }
+    text
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(j) => (&text[..j], text[j..].trim()),
+        None => (text, ""),
+    }
+}
+
+// Operands are separated by commas, `LD I, LONG addr` also by a space.
+fn split_operands(text: &str) -> Vec<String> {
+    text.split(',')
+        .flat_map(|o| o.split_whitespace())
+        .map(|o| o.to_string())
+        .collect()
+}
+
+fn is_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn is_register(text: &str) -> bool {
+    register(text).is_ok()
+}
+
+fn register(text: &str) -> Result<usize, String> {
+    let upper = text.to_uppercase();
+    match upper.strip_prefix('V') {
+        Some(digit) if digit.len() == 1 => usize::from_str_radix(digit, 16)
+            .map_err(|_| format!("Expected a register, found {}", text)),
+        _ => Err(format!("Expected a register, found {}", text)),
+    }
+}
+
+fn register_range(text: &str) -> Result<(usize, usize), String> {
+    let mut parts = text.splitn(2, '-');
+    let x = register(parts.next().unwrap_or(""))?;
+    let y = register(parts.next().unwrap_or(""))?;
+    Ok((x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+    use crate::quirks::Quirks;
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn assembles_labels_and_constants() {
+        let source = "
+            ROW equ 4
+            COLUMN equ ROW     ; constants can name other constants
+            start:  LD I, sprite
+                    LD V0, COLUMN
+                    DRW V0, V1, 3
+            loop: JP loop
+            sprite: db 0xF0, 0b10010000, 240
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            rom,
+            [0xA2, 0x08, 0x60, 0x04, 0xD0, 0x13, 0x12, 0x06, 0xF0, 0x90, 0xF0]
+        );
+    }
+
+    #[test]
+    fn assembles_data_and_long_loads() {
+        let rom = assemble("LD I, LONG data\nhigh: HIGH\ndata: dw 0x1234, high").unwrap();
+        assert_eq!(
+            rom,
+            [0xF0, 0x00, 0x02, 0x06, 0x00, 0xFF, 0x12, 0x34, 0x02, 0x04]
+        );
+        assert_eq!(assemble("ld [i], v1-v3").unwrap(), [0x51, 0x32]);
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        let e = error("CLS\nFROB V1");
+        assert_eq!(e.line, 2);
+        assert_eq!(e.message, "Unknown instruction: FROB V1");
+        assert_eq!(error("a: CLS\na: RET").message, "Label a defined twice");
+        assert_eq!(error("LD V1, 0x100").message, "0x100 does not fit in 0xFF");
+        assert_eq!(error("JP missing").message, "Unknown value missing");
+        assert_eq!(error("LD VG, 1").message, "Expected a register, found VG");
+        assert_eq!(error("1x equ 2").message, "Invalid constant name 1x");
+        assert_eq!(
+            error("A equ B\nB equ A\nJP A").message,
+            "Constant A refers to itself"
+        );
+        assert_eq!(
+            error("JP V1, 0x200").message,
+            "JP V1 needs an address in 0x100-0x1FF"
+        );
+    }
+
+    #[test]
+    fn listings_assemble_to_the_same_rom() {
+        let sources = [
+            "start: LD I, sprite\nDRW V0, V1, 3\nCALL sub\nJP start\n\
+             sub: ADD V0, 1\nRET\nsprite: db 0xF0, 0x90, 0xF0",
+            "LD I, LONG data\nLD [I], V1-V3\nPLANE 3\nEXIT\ndata: dw 0x1234",
+            "SE V1, V2\nSKP V3\nLD V4, K\nLD B, V4\nSCD 2\nLOW\nHIGH\nEXIT",
+        ];
+        let quirks = Quirks {
+            xo_chip: true,
+            ..Quirks::default()
+        };
+        for source in sources.iter() {
+            let rom = assemble(source).unwrap();
+            let listing = disasm::listing(&rom, &quirks);
+            assert_eq!(assemble(&listing).unwrap(), rom, "{}", listing);
+        }
+    }
+}
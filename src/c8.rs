@@ -1,117 +1,465 @@
+use crate::cdp1802::Cdp1802;
+use crate::error::C8Error;
+use crate::instruction::Instruction;
+use crate::quirks::{LoadStore, Quirks};
+use crate::rng::Rng;
+use crate::state::{StateReader, StateWriter};
+use crate::timing::{self, Speed};
+use log::{debug, trace, warn};
+
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+// SUPER-CHIP adds a high resolution mode with twice the resolution in both
+// directions.
+// Source: http://devernay.free.fr/hacks/chip8/schip.txt
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 pub const RAM_SIZE: usize = 4096;
+// XO-CHIP extends the address space to 64 KiB.
+// Source: https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+pub const XO_RAM_SIZE: usize = 65536;
 const REGISTER_COUNT: usize = 16;
+// The stack is an array of 16 16-bit values, used to store the address that
+// the interpreter shoud return to when finished with a subroutine. Chip-8
+// allows for up to 16 levels of nested subroutines.
+// Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+const STACK_SIZE: usize = 16;
+// The COSMAC VIP interpreter keeps its data in the top 352 bytes of RAM: 48
+// bytes for the stack, up to 12 levels of nesting, at 0xEA0-0xECF, its work
+// area at 0xED0-0xEEF, V0-VF at 0xEF0-0xEFF and the display at 0xF00-0xFFF,
+// one bit per pixel and 8 bytes per row. The VIP memory quirk places them
+// there, the stack growing down from 0xED0.
+// Source: https://en.wikipedia.org/wiki/CHIP-8#Memory
+const VIP_STACK_SIZE: usize = 12;
+const VIP_STACK_START: usize = 0xEA0;
+const VIP_STACK_END: usize = 0xED0;
+const VIP_REGISTERS: usize = 0xEF0;
+const VIP_DISPLAY: usize = 0xF00;
+const VIP_DISPLAY_END: usize = 0x1000;
+// Machine code called by SYS that runs for this many instructions, about 10
+// seconds on the VIP, is taken to never return.
+const MACHINE_CODE_LIMIT: u32 = 1_000_000;
 const PROGRAM_START: usize = 0x200;
+// XO-CHIP draws to up to four bitplanes. Every pixel of the display buffer
+// holds one bit per plane, and the combination of set bits picks its colour.
+const PLANE_COUNT: usize = 4;
 // The encoding for each pixel is 0RGB: The upper 8-bits are ignored, the next
 // 8-bits are for the red channel, the next 8-bits afterwards for the green
 // channel, and the lower 8-bits for the blue channel.
 // Source: https://docs.rs/minifb/0.16.0/minifb/struct.Window.html#method.update_with_buffer
-const PX_OFF: u32 = 0xff000000;
-const PX_ON: u32 = 0xffffffff;
+// Plain CHIP-8 and SUPER-CHIP programs only use the first plane, so they stay
+// black and white.
+pub const PALETTE: [u32; 1 << PLANE_COUNT] = [
+    0xff000000, 0xffffffff, 0xffaaaaaa, 0xff555555, // Planes 1 and 2
+    0xffff0000, 0xff00ff00, 0xff0000ff, 0xffffff00, // Plane 3
+    0xff880000, 0xff008800, 0xff000088, 0xff888800, // Plane 4
+    0xffff00ff, 0xff00ffff, 0xff880088, 0xff008888,
+];
+// The small hexadecimal font lives at the start of the interpreter area,
+// followed by the large SUPER-CHIP font.
+const FONT_START: usize = 0x00;
+const BIG_FONT_START: usize = 0x50;
+// Number of RPL user flags that FX75/FX85 can save and restore.
+const RPL_FLAG_COUNT: usize = 16;
+// The XO-CHIP audio pattern is 16 bytes, played back one bit at a time.
+// Source: https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+pub const PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
+// Save states start with a magic number and the version of the format, which
+// is bumped whenever fields are added.
+const STATE_MAGIC: [u8; 4] = *b"C8ST";
+const STATE_VERSION: u16 = 1;
+
+// Chip-8 draws graphics on screen through the use of sprites. A sprite
+// is a group of bytes which are a binary representation of the desired
+// picture. Chip-8 sprites may be up to 15 bytes, for a possible sprite
+// size of 8x15.
+// Programs may also refer to a group of sprites representing the
+// hexadecimal digits 0 through F. These sprites are 5 bytes long, or
+// 8x5 pixels. The data should be stored in the interpreter area of
+// Chip-8 memory (0x000 to 0x1FF).
+// Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+const FONT: [[u8; 5]; 16] = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
+    [0x20, 0x60, 0x20, 0x20, 0x70], // 1
+    [0xF0, 0x10, 0xF0, 0x80, 0xF0], // 2
+    [0xF0, 0x10, 0xF0, 0x10, 0xF0], // 3
+    [0x90, 0x90, 0xF0, 0x10, 0x10], // 4
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0], // 5
+    [0xF0, 0x80, 0xF0, 0x90, 0xF0], // 6
+    [0xF0, 0x10, 0x20, 0x40, 0x40], // 7
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0], // 8
+    [0xF0, 0x90, 0xF0, 0x10, 0xF0], // 9
+    [0xF0, 0x90, 0xF0, 0x90, 0x90], // A
+    [0xE0, 0x90, 0xE0, 0x90, 0xE0], // B
+    [0xF0, 0x80, 0x80, 0x80, 0xF0], // C
+    [0xE0, 0x90, 0x90, 0x90, 0xE0], // D
+    [0xF0, 0x80, 0xF0, 0x80, 0xF0], // E
+    [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
+];
+
+// SUPER-CHIP 1.1 adds 8x10 sprites for the decimal digits 0 through 9, used
+// by FX30. The hexadecimal digits A through F are the ones Octo provides.
+// Source: https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/SuperChip.md
+const BIG_FONT: [[u8; 10]; 16] = [
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C], // 0
+    [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C], // 1
+    [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF], // 2
+    [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C], // 3
+    [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06], // 4
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C], // 5
+    [0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C], // 6
+    [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60], // 7
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C], // 8
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C], // 9
+    [0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3], // A
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC], // B
+    [0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C], // C
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC], // D
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF], // E
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0], // F
+];
+
+/// Progress of a pending `LD Vx, K` (FX0A). While it is pending `C8::run`
+/// executes no instructions, the timers keep running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyWait {
+    /// Waiting for a key to be pressed, to store it in Vx. The keys in the
+    /// mask, bit k for key k, were already held when the wait began and
+    /// only count once they are released and pressed again.
+    Press(usize, u16),
+    /// The key was pressed, it is stored in Vx once it is released.
+    Release(usize, u8),
+}
+
+impl KeyWait {
+    /// The register the key is stored in.
+    pub fn register(&self) -> usize {
+        match self {
+            KeyWait::Press(x, _) | KeyWait::Release(x, _) => *x,
+        }
+    }
+}
 
 // Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+/// A CHIP-8 machine: memory, registers, timers and the display buffer.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct C8 {
-    ram: [u8; RAM_SIZE],                // RAM
-    pub v: [u8; REGISTER_COUNT],        // Vx Registers, VF = Special Flag
-    pub display: [u32; WIDTH * HEIGHT], // Display Buffer
-    i: u16,                             // Used to store Memory Addresses
-    pub dt: u8,                         // Delay Timer
-    pub st: u8,                         // Sound Timer
-    stack: Vec<u16>,                    // Return Stack
-    pub pc: u16,                        // Program Counter
+    ram: Vec<u8>,                        // RAM
+    pub v: [u8; REGISTER_COUNT],         // Vx Registers, VF = Special Flag
+    display: Vec<u8>,                    // Display Buffer, bitplanes per pixel
+    i: u16,                              // Used to store Memory Addresses
+    pub dt: u8,                          // Delay Timer
+    pub st: u8,                          // Sound Timer
+    stack: Vec<u16>,                     // Return Stack
+    pub pc: u16,                         // Program Counter
+    pub quirks: Quirks,                  // Interpreter specific behaviour
+    vblank: bool,                        // Display interrupt since last DRW
+    hires: bool,                         // SUPER-CHIP 128x64 mode
+    exited: bool,                        // SUPER-CHIP EXIT executed
+    rpl: [u8; RPL_FLAG_COUNT],           // SUPER-CHIP RPL user flags
+    planes: u8,                          // XO-CHIP bitplanes selected for drawing
+    pattern: Option<[u8; PATTERN_SIZE]>, // XO-CHIP audio pattern, if loaded
+    pitch: u8,                           // XO-CHIP audio pattern playback pitch
+    key_wait: Option<KeyWait>,           // Pending LD Vx, K
+    rng: Rng,                            // Random numbers for RND Vx, byte
+    #[cfg_attr(feature = "serde", serde(skip))]
+    writes: Option<Vec<usize>>, // RAM written by the last instruction, if logged
+    #[cfg_attr(feature = "serde", serde(skip))]
+    speed: Speed, // Instructions executed by run_frame
+    #[cfg_attr(feature = "serde", serde(skip))]
+    carry: u32, // Instructions or VIP cycles carried over from earlier frames
+    #[cfg_attr(feature = "serde", serde(skip))]
+    frame_left: u32, // Instructions left in a frame ended early
+    #[cfg_attr(feature = "serde", serde(skip))]
+    machine_cycles: u32, // CDP1802 cycles spent by the last SYS
 }
 
 // RAM Methods
 impl C8 {
-    pub fn load_ram(&mut self, rom: &Vec<u8>) {
-        // Chip-8 draws graphics on screen through the use of sprites. A sprite
-        // is a group of bytes which are a binary representation of the desired
-        // picture. Chip-8 sprites may be up to 15 bytes, for a possible sprite
-        // size of 8x15.
-        // Programs may also refer to a group of sprites representing the
-        // hexadecimal digits 0 through F. These sprites are 5 bytes long, or
-        // 8x5 pixels. The data should be stored in the interpreter area of
-        // Chip-8 memory (0x000 to 0x1FF).
-        // Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
-        let sprites: [[u8; 5]; 16] = [
-            [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
-            [0x20, 0x60, 0x20, 0x20, 0x70], // 1
-            [0xF0, 0x10, 0xF0, 0x80, 0xF0], // 2
-            [0xF0, 0x10, 0xF0, 0x10, 0xF0], // 3
-            [0x90, 0x90, 0xF0, 0x10, 0x10], // 4
-            [0xF0, 0x80, 0xF0, 0x10, 0xF0], // 5
-            [0xF0, 0x80, 0xF0, 0x90, 0xF0], // 6
-            [0xF0, 0x10, 0x20, 0x40, 0x40], // 7
-            [0xF0, 0x90, 0xF0, 0x90, 0xF0], // 8
-            [0xF0, 0x90, 0xF0, 0x10, 0xF0], // 9
-            [0xF0, 0x90, 0xF0, 0x90, 0x90], // A
-            [0xE0, 0x90, 0xE0, 0x90, 0xE0], // B
-            [0xF0, 0x80, 0x80, 0x80, 0xF0], // C
-            [0xE0, 0x90, 0x90, 0x90, 0xE0], // D
-            [0xF0, 0x80, 0xF0, 0x80, 0xF0], // E
-            [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
-        ];
-        for la in 0..80 {
-            let lc = sprites[la / 5][la % 5];
-            println!("[Byte Load] | {:#x} : {:#x}", la, lc);
-            self.ram[la] = lc;
+    /// Loads the hexadecimal font into the interpreter area and copies `rom`
+    /// into memory starting at 0x200.
+    pub fn load_ram(&mut self, rom: &[u8]) -> Result<(), C8Error> {
+        // With the VIP memory quirk the program ends where the stack starts.
+        let end = if self.quirks.vip_memory {
+            VIP_STACK_START
+        } else {
+            self.ram.len()
+        };
+        let max = end - PROGRAM_START;
+        if rom.len() > max {
+            return Err(C8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
         }
 
-        for (j, lc) in rom.into_iter().enumerate() {
+        for (j, lc) in FONT.iter().flatten().enumerate() {
+            let la = FONT_START + j;
+            trace!(target: "memory", "Byte load | {:#x} : {:#x}", la, lc);
+            self.ram[la] = *lc;
+        }
+
+        for (j, lc) in BIG_FONT.iter().flatten().enumerate() {
+            let la = BIG_FONT_START + j;
+            trace!(target: "memory", "Byte load | {:#x} : {:#x}", la, lc);
+            self.ram[la] = *lc;
+        }
+
+        for (j, lc) in rom.iter().enumerate() {
             let la = PROGRAM_START + j;
-            if la >= RAM_SIZE {
-                panic!("Out of memory: Program too large");
+            trace!(target: "memory", "Byte load | {:#x} : {:#x}", la, lc);
+            self.ram[la] = *lc;
+        }
+        debug!(
+            target: "memory",
+            "Loaded {} bytes of ROM at {:#05x}",
+            rom.len(),
+            PROGRAM_START
+        );
+
+        Ok(())
+    }
+
+    // Checks that the `len` bytes starting at `addr` lie inside RAM before an
+    // instruction reads or writes them.
+    fn check_ram(&self, addr: usize, len: usize, inst: u16) -> Result<(), C8Error> {
+        if addr + len > self.ram.len() {
+            return Err(C8Error::MemoryOutOfBounds {
+                pc: self.pc,
+                opcode: inst,
+                addr: addr.max(self.ram.len()),
+            });
+        }
+
+        Ok(())
+    }
+
+    // Instructions write to RAM only through here, so the writes can be
+    // logged for watchpoints. The address has been checked with check_ram.
+    fn write_ram(&mut self, addr: usize, value: u8) {
+        self.ram[addr] = value;
+        if self.quirks.vip_memory {
+            self.write_vip_memory(addr, value);
+        }
+        if let Some(writes) = self.writes.as_mut() {
+            writes.push(addr);
+        }
+    }
+
+    /// Starts or stops logging the addresses written by each instruction,
+    /// which `writes` returns.
+    pub fn log_writes(&mut self, enabled: bool) {
+        self.writes = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Addresses of RAM written by the last instruction executed, while
+    /// writes are logged.
+    pub fn writes(&self) -> &[usize] {
+        match &self.writes {
+            Some(writes) => writes,
+            None => &[],
+        }
+    }
+
+    // The VIP memory quirk only has room for 12 return addresses.
+    fn stack_size(&self) -> usize {
+        if self.quirks.vip_memory {
+            VIP_STACK_SIZE
+        } else {
+            STACK_SIZE
+        }
+    }
+
+    // With the VIP memory quirk, a write to where the interpreter keeps the
+    // stack, the registers or the display changes them.
+    fn write_vip_memory(&mut self, addr: usize, value: u8) {
+        if (VIP_STACK_START..VIP_STACK_END).contains(&addr) {
+            let level = (VIP_STACK_END - 1 - addr) / 2;
+            let high = VIP_STACK_END - 2 * (level + 1);
+            if let Some(entry) = self.stack.get_mut(level) {
+                *entry = u16::from_be_bytes([self.ram[high], self.ram[high + 1]]);
+            }
+        } else if (VIP_REGISTERS..VIP_DISPLAY).contains(&addr) {
+            self.v[addr - VIP_REGISTERS] = value;
+        } else if (VIP_DISPLAY..VIP_DISPLAY_END).contains(&addr) && !self.hires {
+            // Only the first bitplane is mapped, the others are kept.
+            let first = (addr - VIP_DISPLAY) * 8;
+            for (bit, px) in self.display[first..first + 8].iter_mut().enumerate() {
+                *px = (*px & !1) | ((value >> (7 - bit)) & 1);
             }
+        }
+    }
 
-            println!("[Byte Load] | {:#x} : {:#x}", la, lc);
-            self.ram[la] = *lc;
+    // With the VIP memory quirk, copies the stack, the registers and, if
+    // `display` is set, the display to where the interpreter keeps them in
+    // RAM. The display is only mapped in the 64x32 resolution.
+    fn map_vip_memory(&mut self, display: bool) {
+        if !self.quirks.vip_memory {
+            return;
+        }
+        for (level, addr) in self.stack.iter().enumerate() {
+            let high = VIP_STACK_END - 2 * (level + 1);
+            self.ram[high..high + 2].copy_from_slice(&addr.to_be_bytes());
         }
+        self.ram[VIP_REGISTERS..VIP_DISPLAY].copy_from_slice(&self.v);
+        if display && !self.hires {
+            for (byte, pixels) in self.ram[VIP_DISPLAY..VIP_DISPLAY_END]
+                .iter_mut()
+                .zip(self.display.chunks(8))
+            {
+                *byte = pixels.iter().fold(0, |byte, px| (byte << 1) | (px & 1));
+            }
+        }
+    }
+
+    /// Size of the address space: 4 KiB, or 64 KiB with XO-CHIP.
+    pub fn ram_size(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// The whole address space, including the interpreter area and fonts.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// The whole address space, for debuggers and tools that patch memory.
+    /// Patches bypass the VIP memory quirk, the registers, the stack and the
+    /// display keep their values.
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
     }
 }
 
 // CPU Methods
 impl C8 {
-    pub fn run(&mut self, key_press: &[bool; 16]) -> usize {
-        let mut wait_for_key: usize = 0;
+    /// The address register I.
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// Sets the address register I.
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    /// Addresses of the `CALL` instructions of the subroutines currently
+    /// running, innermost last. `RET` continues after them.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// The pending `LD Vx, K` the program is blocked on, if any.
+    pub fn key_wait(&self) -> Option<KeyWait> {
+        self.key_wait
+    }
+
+    // Advances a pending `LD Vx, K` with the keys currently held. The lowest
+    // key held is taken when several are.
+    fn wait_for_key(&mut self, wait: KeyWait, key_press: &[bool; 16]) {
+        self.key_wait = match wait {
+            KeyWait::Press(x, held) => {
+                // A key held since the wait began has to be released first.
+                let held = held & Self::key_mask(key_press);
+                let pressed = (0..16).find(|&k| key_press[k] && held & (1 << k) == 0);
+                match pressed {
+                    // The VIP waits for the key to be released again before
+                    // it continues.
+                    Some(key) if self.quirks.wait_for_release => {
+                        debug!(target: "input", "Key {:X} pressed for V{:X}", key, x);
+                        Some(KeyWait::Release(x, key as u8))
+                    }
+                    Some(key) => {
+                        self.store_key(x, key as u8);
+                        None
+                    }
+                    None => Some(KeyWait::Press(x, held)),
+                }
+            }
+            KeyWait::Release(x, key) if !key_press[key as usize] => {
+                self.store_key(x, key);
+                None
+            }
+            wait => Some(wait),
+        };
+    }
+
+    fn store_key(&mut self, x: usize, key: u8) {
+        debug!(target: "input", "Key {:X} stored in V{:X}", key, x);
+        self.v[x] = key;
+        self.map_vip_memory(false);
+    }
+
+    // Applies the load/store quirk to I after FX55 and FX65 accessed registers
+    // V0 through Vx.
+    fn increment_i(&mut self, x: usize) {
+        match self.quirks.load_store {
+            LoadStore::Unchanged => (),
+            LoadStore::IncrementByX => self.i = self.i.wrapping_add(x as u16),
+            LoadStore::IncrementByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    /// Fetches, decodes and executes a single instruction. `key_press` holds
+    /// the current state of the 16 keys of the hexadecimal keypad.
+    ///
+    /// While an `LD Vx, K` is pending no instruction is executed, the call
+    /// only checks the keys for the one it waits for. On a fault the program
+    /// counter is left at the faulting instruction.
+    pub fn run(&mut self, key_press: &[bool; 16]) -> Result<(), C8Error> {
+        if let Some(wait) = self.key_wait {
+            self.wait_for_key(wait, key_press);
+            return Ok(());
+        }
+        let instruction = self.fetch()?;
+        self.execute(instruction, key_press)
+    }
 
+    /// Fetches and decodes the instruction at the program counter.
+    pub fn fetch(&self) -> Result<Instruction, C8Error> {
         // All instructions are 2 bytes long and are stored
         // most-significant-byte first. In memory, the first byte of each
         // instruction should be located at an even addresses. If a program
         // includes sprite data, it should be padded so any instructions
         // following it will be properly situated in RAM.
         // Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+        self.check_ram(self.pc as usize, 2, 0)?;
         let b1 = self.ram[self.pc as usize] as u16;
         let b2 = self.ram[self.pc as usize + 1] as u16;
-        let inst = (b1 << 8) | b2;
-
-        // In these listings, the following variables are used:
-        // nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
-        // n or nibble - A 4-bit value, the lowest 4 bits of the instruction
-        // x - A 4-bit value, the lower 4 bits of the high byte of the instruction
-        // y - A 4-bit value, the upper 4 bits of the low byte of the instruction
-        // kk or byte - An 8-bit value, the lowest 8 bits of the instruction
-        // Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
-        let nnn = (inst & 0x0FFF) as u16;
-        let n = (inst & 0x000F) as u8;
-        let x = ((inst & 0x0F00) >> 8) as usize;
-        let y = ((inst & 0x00F0) >> 4) as usize;
-        let kk = (inst & 0x00FF) as u8;
-
-        let inst_tup = (
-            ((inst & 0xF000) >> 12) as u8,
-            ((inst & 0x0F00) >> 8) as u8,
-            ((inst & 0x00F0) >> 4) as u8,
-            (inst & 0x000F) as u8,
-        );
+
+        Ok(Instruction::decode((b1 << 8) | b2))
+    }
+
+    /// Executes `instruction` as if it had been fetched from the program
+    /// counter, with the same results as `run`, also while an `LD Vx, K` is
+    /// pending.
+    pub fn execute(
+        &mut self,
+        instruction: Instruction,
+        key_press: &[bool; 16],
+    ) -> Result<(), C8Error> {
+        let inst = instruction.encode();
+        if let Some(writes) = self.writes.as_mut() {
+            writes.clear();
+        }
+
         enum ProgramCounter {
             Next,
             Skip,
             Jump(u16),
         }
 
-        let pc_change: ProgramCounter = match inst_tup {
-            /*(0x0, _, _, _) => {
+        // With the display wait quirk DRW waits for the display interrupt
+        // before drawing, as the VIP interpreter did. The instruction is
+        // retried on the next call until `tick_timers` signals the interrupt.
+        let is_draw = matches!(instruction, Instruction::Draw(..));
+        if is_draw && self.quirks.display_wait && !self.vblank {
+            return Ok(());
+        }
+
+        let pc_change: ProgramCounter = match instruction {
+            Instruction::Sys(nnn) if self.quirks.machine_code => {
                 // SYS addr (Call)
                 // Jump to a machine code routine at nnn.
                 // This instruction is only used on the old computers on which
@@ -128,21 +476,98 @@ impl C8 {
                 // unimplemented on modern interpreters.
                 // Source: http://mattmik.com/files/chip8/mastering/chip8.html
 
+                ProgramCounter::Jump(self.call_machine_code(nnn, inst)?)
+            }
+
+            Instruction::Sys(nnn) => {
+                // Without machine code the call is skipped, like modern
+                // interpreters do, but a program relying on it likely
+                // misbehaves.
+                warn!(target: "cpu", "Ignored SYS {:#05x} at {:#05x}", nnn, self.pc);
+
                 ProgramCounter::Next
-            }*/
-            (0x0, 0x0, 0xE, 0x0) => {
+            }
+
+            Instruction::Cls => {
                 // CLS (Display)
                 // Clear the display.
 
-                let sz = WIDTH * HEIGHT;
-                for j in 0..sz {
-                    self.display[j] = PX_OFF;
+                // XO-CHIP only clears the selected bitplanes.
+                let planes = self.planes;
+                for px in self.display.iter_mut() {
+                    *px &= !planes;
                 }
+                trace!(target: "display", "Cleared bitplanes {:#x}", planes);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::ScrollDown(n) => {
+                // SCD nibble (Display, SUPER-CHIP)
+                // Scroll display N lines down.
+
+                self.scroll(0, n as isize);
 
                 ProgramCounter::Next
             }
 
-            (0x0, 0x0, 0xE, 0xE) => {
+            Instruction::ScrollUp(n) if self.quirks.xo_chip => {
+                // SCU nibble (Display, XO-CHIP)
+                // Scroll display N lines up.
+
+                self.scroll(0, -(n as isize));
+
+                ProgramCounter::Next
+            }
+
+            Instruction::ScrollRight => {
+                // SCR (Display, SUPER-CHIP)
+                // Scroll display 4 pixels right.
+
+                self.scroll(4, 0);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::ScrollLeft => {
+                // SCL (Display, SUPER-CHIP)
+                // Scroll display 4 pixels left.
+
+                self.scroll(-4, 0);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::Exit => {
+                // EXIT (Flow, SUPER-CHIP)
+                // Exit the interpreter.
+
+                // The program counter stays on EXIT, so running the machine
+                // any further keeps it stopped here.
+                self.exited = true;
+
+                ProgramCounter::Jump(self.pc)
+            }
+
+            Instruction::Low => {
+                // LOW (Display, SUPER-CHIP)
+                // Disable extended screen mode.
+
+                self.set_hires(false);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::High => {
+                // HIGH (Display, SUPER-CHIP)
+                // Enable extended screen mode for full-screen graphics.
+
+                self.set_hires(true);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::Ret => {
                 // RET (Flow)
                 // Return from a subroutine.
 
@@ -155,16 +580,16 @@ impl C8 {
                 // and program execution should proceed at the point from which
                 // the last subroutine call occurred.
                 // Source: http://mattmik.com/files/chip8/mastering/chip8.html
-                let addr = self
-                    .stack
-                    .pop()
-                    .expect("Empty Stack: Cannot return from Subroutine");
+                let addr = self.stack.pop().ok_or(C8Error::StackUnderflow {
+                    pc: self.pc,
+                    opcode: inst,
+                })?;
                 self.pc = addr;
 
                 ProgramCounter::Next
             }
 
-            (0x1, _, _, _) => {
+            Instruction::Jump(nnn) => {
                 // JP addr (Flow)
                 // Jump to location nnn.
 
@@ -173,7 +598,7 @@ impl C8 {
                 ProgramCounter::Jump(nnn)
             }
 
-            (0x2, _, _, _) => {
+            Instruction::Call(nnn) => {
                 // CALL addr (Flow)
                 // Call subroutine at nnn.
 
@@ -184,12 +609,18 @@ impl C8 {
                 // CHIP-8 program execution will then continue from this address
                 // until a termination instruction is found.
                 // Source: http://mattmik.com/files/chip8/mastering/chip8.html
+                if self.stack.len() >= self.stack_size() {
+                    return Err(C8Error::StackOverflow {
+                        pc: self.pc,
+                        opcode: inst,
+                    });
+                }
                 self.stack.push(self.pc);
 
                 ProgramCounter::Jump(nnn)
             }
 
-            (0x3, _, _, _) => {
+            Instruction::SkipEqByte(x, kk) => {
                 // SE Vx, byte (Cond)
                 // Skip next instruction if Vx = kk.
                 // The interpreter compares register Vx to kk, and if they are
@@ -202,7 +633,7 @@ impl C8 {
                 }
             }
 
-            (0x4, _, _, _) => {
+            Instruction::SkipNeByte(x, kk) => {
                 // SNE Vx, byte (Cond)
                 // Skip next instruction if Vx != kk.
 
@@ -216,7 +647,7 @@ impl C8 {
                 }
             }
 
-            (0x5, _, _, 0x0) => {
+            Instruction::SkipEqReg(x, y) => {
                 // SE Vx, Vy (Cond)
                 // Skip next instruction if Vx = Vy.
 
@@ -230,7 +661,37 @@ impl C8 {
                 }
             }
 
-            (0x6, _, _, _) => {
+            Instruction::StoreRange(x, y) if self.quirks.xo_chip => {
+                // LD [I], Vx-Vy (MEM, XO-CHIP)
+                // Store registers Vx through Vy in memory starting at location
+                // I.
+
+                // The registers are stored in the order given, so x may be
+                // larger than y to store them backwards. I is not changed.
+                let regs = Self::register_range(x, y);
+                self.check_ram(self.i as usize, regs.len(), inst)?;
+                for (j, r) in regs.into_iter().enumerate() {
+                    self.write_ram(self.i as usize + j, self.v[r]);
+                }
+
+                ProgramCounter::Next
+            }
+
+            Instruction::LoadRange(x, y) if self.quirks.xo_chip => {
+                // LD Vx-Vy, [I] (MEM, XO-CHIP)
+                // Read registers Vx through Vy from memory starting at
+                // location I.
+
+                let regs = Self::register_range(x, y);
+                self.check_ram(self.i as usize, regs.len(), inst)?;
+                for (j, r) in regs.into_iter().enumerate() {
+                    self.v[r] = self.ram[self.i as usize + j];
+                }
+
+                ProgramCounter::Next
+            }
+
+            Instruction::LoadByte(x, kk) => {
                 // LD Vx, byte (Const)
                 // Set Vx = kk.
 
@@ -241,7 +702,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0x7, _, _, _) => {
+            Instruction::AddByte(x, kk) => {
                 // ADD Vx, byte (Const)
                 // Set Vx = Vx + kk.
 
@@ -263,7 +724,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x0) => {
+            Instruction::LoadReg(x, y) => {
                 // LD Vx, Vy (Assign)
                 // Set Vx = Vy.
 
@@ -274,7 +735,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x1) => {
+            Instruction::Or(x, y) => {
                 // OR Vx, Vy (BitOp)
                 // Set Vx = Vx OR Vy.
 
@@ -283,12 +744,15 @@ impl C8 {
                 // bits from two values, and if either bit is 1, then the same
                 // bit in the result is also 1. Otherwise, it is 0.
 
-                self.v[x] = self.v[x] | self.v[y];
+                self.v[x] |= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
 
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x2) => {
+            Instruction::And(x, y) => {
                 // AND Vx, Vy (BitOp)
                 // Set Vx = Vx AND Vy.
 
@@ -298,12 +762,15 @@ impl C8 {
                 // then the same bit in the result is also 1. Otherwise, it is
                 // 0.
 
-                self.v[x] = self.v[x] & self.v[y];
+                self.v[x] &= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
 
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x3) => {
+            Instruction::Xor(x, y) => {
                 // XOR Vx, Vy (BitOp)
                 // Set Vx = Vx XOR Vy.
 
@@ -313,12 +780,15 @@ impl C8 {
                 // both the same, then the corresponding bit in the result is
                 // set to 1. Otherwise, it is 0.
 
-                self.v[x] = self.v[x] ^ self.v[y];
+                self.v[x] ^= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
 
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x4) => {
+            Instruction::AddReg(x, y) => {
                 // ADD Vx, Vy (Math)
                 // Set Vx = Vx + Vy, set VF = carry.
 
@@ -341,7 +811,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x5) => {
+            Instruction::Sub(x, y) => {
                 // SUB Vx, Vy (Math)
                 // Set Vx = Vx - Vy, set VF = NOT borrow.
 
@@ -362,7 +832,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x6) => {
+            Instruction::ShiftRight(x, y) => {
                 // SHR Vx {, Vy} (BitOp)
                 // Set Vx = Vx SHR 1.
 
@@ -379,15 +849,22 @@ impl C8 {
                 // implement a rotate_right function, which may be what you want
                 // instead.
                 // Source: https://doc.rust-lang.org/std/primitive.u8.html#method.wrapping_shr
-                let res = self.v[x].wrapping_shr(1);
+                // The original interpreter shifted Vy and stored the result in
+                // Vx, CHIP-48 and SUPER-CHIP shift Vx in place.
+                let src = if self.quirks.shift_uses_vy {
+                    self.v[y]
+                } else {
+                    self.v[x]
+                };
+                let res = src.wrapping_shr(1);
 
-                self.v[0xF] = self.v[x] & 0b1;
+                self.v[0xF] = src & 0b1;
                 self.v[x] = res;
 
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0x7) => {
+            Instruction::SubN(x, y) => {
                 // SUBN Vx, Vy (Math)
                 // Set Vx = Vy - Vx, set VF = NOT borrow.
 
@@ -408,7 +885,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0x8, _, _, 0xE) => {
+            Instruction::ShiftLeft(x, y) => {
                 // SHL Vx {, Vy} (BitOp)
                 // Set Vx = Vx SHL 1.
 
@@ -424,16 +901,21 @@ impl C8 {
                 // the other end. The primitive integer types all implement a
                 // rotate_left function, which may be what you want instead.
                 // Source: https://doc.rust-lang.org/std/primitive.u8.html#method.wrapping_shl
-                let res = self.v[x].wrapping_shl(1);
+                let src = if self.quirks.shift_uses_vy {
+                    self.v[y]
+                } else {
+                    self.v[x]
+                };
+                let res = src.wrapping_shl(1);
 
                 // 128 = 0b1000_0000 = 0x80
-                self.v[0xF] = (self.v[x] & 0x80) >> 7;
+                self.v[0xF] = (src & 0x80) >> 7;
                 self.v[x] = res;
 
                 ProgramCounter::Next
             }
 
-            (0x9, _, _, 0x0) => {
+            Instruction::SkipNeReg(x, y) => {
                 // SNE Vx, Vy (Cond)
                 // Skip next instruction if Vx != Vy.
 
@@ -447,7 +929,7 @@ impl C8 {
                 }
             }
 
-            (0xA, _, _, _) => {
+            Instruction::LoadI(nnn) => {
                 // LD I, addr (MEM)
                 // Set I = nnn.
 
@@ -458,16 +940,25 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0xB, _, _, _) => {
+            Instruction::JumpOffset(nnn) => {
                 // JP V0, addr (Flow)
                 // Jump to location nnn + V0.
 
                 // The program counter is set to nnn plus the value of V0.
 
-                ProgramCounter::Jump(self.v[0] as u16 + nnn)
+                // CHIP-48 and SUPER-CHIP read this as BXNN and add Vx instead,
+                // where x is the highest nibble of nnn.
+                let x = (nnn >> 8) as usize;
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+
+                ProgramCounter::Jump(offset as u16 + nnn)
             }
 
-            (0xC, _, _, _) => {
+            Instruction::Random(x, kk) => {
                 // RND Vx, byte (Rand)
                 // Set Vx = random byte AND kk.
 
@@ -475,13 +966,13 @@ impl C8 {
                 // which is then ANDed with the value kk. The results are stored
                 // in Vx. See instruction 8xy2 for more information on AND.
 
-                let rnd = rand::random::<u8>();
+                let rnd = self.rng.next_u8();
                 self.v[x] = rnd & kk;
 
                 ProgramCounter::Next
             }
 
-            (0xD, _, _, _) => {
+            Instruction::Draw(x, y, n) => {
                 // DRW Vx, Vy, nibble (Disp)
                 // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
 
@@ -496,32 +987,58 @@ impl C8 {
                 // 2.4, Display, for more information on the Chip-8 screen and
                 // sprites.
 
-                let inx = self.v[x];
-                let iny = self.v[y];
-                let mut collision = 0;
+                // DRW Vx, Vy, 0 (Disp, SUPER-CHIP)
+                // Show 16x16 sprite, stored as 16 pairs of bytes.
 
-                let mut printer = Vec::<u8>::new();
-                for j in 0..n as usize {
-                    printer.push(self.ram[self.i as usize + j]);
-                }
+                // With several XO-CHIP bitplanes selected, the sprite data for
+                // each plane follows the previous one in memory, lowest plane
+                // first.
 
-                for (k, b) in printer.iter().enumerate() {
-                    for j in 0..8 {
-                        let cux = inx.wrapping_add(j) % WIDTH as u8;
-                        let cuy = iny.wrapping_add(k as u8) % HEIGHT as u8;
+                self.vblank = false;
 
-                        let cord = (cuy as usize * WIDTH) + cux as usize;
-                        let is_old_set = self.display[cord] == PX_ON;
+                let (width, height) = (self.width(), self.height());
 
-                        self.display[cord] = if (b >> (7 - j)) & 0b1 == 1 {
-                            if is_old_set {
-                                collision |= 1;
-                                PX_OFF
-                            } else {
-                                PX_ON
-                            }
+                // The starting position always wraps around, only the pixels
+                // past the edges are either wrapped or clipped.
+                let inx = self.v[x] as usize % width;
+                let iny = self.v[y] as usize % height;
+                let mut collision = 0;
+
+                // Each row of the sprite is kept left aligned in 16 bits, so 8
+                // and 16 pixel wide sprites are drawn the same way.
+                let (sprite_width, rows) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let row_bytes = sprite_width / 8;
+                let selected = (0..PLANE_COUNT).filter(|p| self.planes & (1 << p) != 0);
+                let planes: Vec<u8> = selected.map(|p| 1 << p).collect();
+                self.check_ram(self.i as usize, planes.len() * rows * row_bytes, inst)?;
+
+                let mut la = self.i as usize;
+                for plane in planes {
+                    let mut printer = Vec::<u16>::new();
+                    for _ in 0..rows {
+                        let row = if row_bytes == 2 {
+                            (self.ram[la] as u16) << 8 | self.ram[la + 1] as u16
                         } else {
-                            self.display[cord]
+                            (self.ram[la] as u16) << 8
+                        };
+                        printer.push(row);
+                        la += row_bytes;
+                    }
+
+                    for (k, b) in printer.iter().enumerate() {
+                        for j in 0..sprite_width {
+                            let (cux, cuy) = (inx + j, iny + k);
+                            if self.quirks.clip_sprites && (cux >= width || cuy >= height) {
+                                continue;
+                            }
+
+                            if (b >> (15 - j)) & 0b1 == 1 {
+                                let cord = (cuy % height * width) + cux % width;
+                                if self.display[cord] & plane != 0 {
+                                    collision |= 1;
+                                }
+                                self.display[cord] ^= plane;
+                            }
                         }
                     }
                 }
@@ -531,7 +1048,7 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0xE, _, 0x9, 0xE) => {
+            Instruction::SkipKey(x) => {
                 // SKP Vx (KeyOp)
                 // Skip next instruction if key with the value of Vx is pressed.
 
@@ -539,14 +1056,16 @@ impl C8 {
                 // value of Vx is currently in the down position, PC is
                 // increased by 2.
 
-                if key_press[self.v[x] as usize] {
+                // Only the low nibble of Vx names a key, like the digit of
+                // `LD F, Vx`.
+                if key_press[self.v[x] as usize & 0xF] {
                     ProgramCounter::Skip
                 } else {
                     ProgramCounter::Next
                 }
             }
 
-            (0xE, _, 0xA, 0x1) => {
+            Instruction::SkipNotKey(x) => {
                 // SKNP Vx (KeyOp)
                 // Skip next instruction if key with the value of Vx is not pressed.
 
@@ -554,14 +1073,39 @@ impl C8 {
                 // value of Vx is currently in the up position, PC is increased
                 // by 2.
 
-                if !key_press[self.v[x] as usize] {
+                if !key_press[self.v[x] as usize & 0xF] {
                     ProgramCounter::Skip
                 } else {
                     ProgramCounter::Next
                 }
             }
 
-            (0xF, _, 0x0, 0x7) => {
+            Instruction::LoadILong if self.quirks.xo_chip => {
+                // LD I, long (MEM, XO-CHIP)
+                // Set I = the 16-bit address stored in the next two bytes.
+
+                // This is the only instruction that is 4 bytes long.
+                self.check_ram(self.pc as usize + 2, 2, inst)?;
+                let hi = self.ram[self.pc as usize + 2] as u16;
+                let lo = self.ram[self.pc as usize + 3] as u16;
+                self.i = (hi << 8) | lo;
+
+                ProgramCounter::Jump(self.pc.wrapping_add(4))
+            }
+
+            Instruction::Plane(mask) if self.quirks.xo_chip => {
+                // PLANE n (Disp, XO-CHIP)
+                // Select the bitplanes that drawing, clearing and scrolling
+                // affect.
+
+                // The bitmask in x selects any combination of the planes, 0
+                // selects none of them.
+                self.planes = mask;
+
+                ProgramCounter::Next
+            }
+
+            Instruction::LoadDelay(x) => {
                 // LD Vx, DT (Timer)
                 // Set Vx = delay timer value.
 
@@ -572,19 +1116,22 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x0, 0xA) => {
+            Instruction::WaitKey(x) => {
                 // LD Vx, K (KeyOp)
                 // Wait for a key press, store the value of the key in Vx.
 
                 // All execution stops until a key is pressed, then the value of
                 // that key is stored in Vx.
 
-                wait_for_key = x;
+                // Only a new press counts, a key that is already held when
+                // the wait begins is ignored until it is released.
+                self.key_wait = Some(KeyWait::Press(x, Self::key_mask(key_press)));
+                debug!(target: "input", "Waiting for a key for V{:X}", x);
 
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x1, 0x5) => {
+            Instruction::SetDelay(x) => {
                 // LD DT, Vx (Timer)
                 // Set delay timer = Vx.
 
@@ -596,30 +1143,57 @@ impl C8 {
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x1, 0x8) => {
+            Instruction::Audio if self.quirks.xo_chip => {
+                // AUDIO (Sound, XO-CHIP)
+                // Load the 16-byte audio pattern buffer from memory at I.
+
+                // While the sound timer is non-zero the pattern is played one
+                // bit at a time, most significant bit of the first byte first.
+                self.check_ram(self.i as usize, PATTERN_SIZE, inst)?;
+                let mut pattern = [0; PATTERN_SIZE];
+                let start = self.i as usize;
+                pattern.copy_from_slice(&self.ram[start..start + PATTERN_SIZE]);
+                self.pattern = Some(pattern);
+                debug!(target: "audio", "Loaded audio pattern {:02x?}", pattern);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::Pitch(x) if self.quirks.xo_chip => {
+                // PITCH Vx (Sound, XO-CHIP)
+                // Set the audio pattern playback rate from Vx.
+
+                self.pitch = self.v[x];
+                debug!(target: "audio", "Pitch set to {}", self.pitch);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::SetSound(x) => {
                 // LD ST, Vx (Sound)
                 // Set sound timer = Vx.
 
                 // ST is set equal to the value of Vx.
 
                 self.st = self.v[x];
+                trace!(target: "audio", "Sound timer set to {}", self.st);
 
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x1, 0xE) => {
+            Instruction::AddI(x) => {
                 // ADD I, Vx (MEM)
                 // Set I = I + Vx.
 
                 // The values of I and Vx are added, and the results are stored
                 // in I.
 
-                self.i += self.v[x] as u16;
+                self.i = self.i.wrapping_add(self.v[x] as u16);
 
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x2, 0x9) => {
+            Instruction::LoadFont(x) => {
                 // LD F, Vx (MEM)
                 // Set I = location of sprite for digit Vx.
 
@@ -629,11 +1203,20 @@ impl C8 {
 
                 // Mutliplying by 5 because each character occupies 5
                 // consecutive spots starting from 0.
-                self.i = self.v[x] as u16 * 5;
+                self.i = (FONT_START + (self.v[x] as usize & 0xF) * 5) as u16;
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x3, 0x3) => {
+            Instruction::LoadBigFont(x) => {
+                // LD HF, Vx (MEM, SUPER-CHIP)
+                // Point I to 10-byte font sprite for digit Vx.
+
+                self.i = (BIG_FONT_START + (self.v[x] as usize & 0xF) * 10) as u16;
+
+                ProgramCounter::Next
+            }
+
+            Instruction::Bcd(x) => {
                 // LD B, Vx (BCD)
                 // Store BCD representation of Vx in memory locations I, I+1, and I+2.
 
@@ -641,73 +1224,1063 @@ impl C8 {
                 // hundreds digit in memory at location in I, the tens digit at
                 // location I+1, and the ones digit at location I+2.
 
-                self.ram[self.i as usize] = self.v[x] / 100;
-                self.ram[self.i as usize + 1] = (self.v[x] % 100) / 10;
-                self.ram[self.i as usize + 2] = self.v[x] % 10;
+                self.check_ram(self.i as usize, 3, inst)?;
+                self.write_ram(self.i as usize, self.v[x] / 100);
+                self.write_ram(self.i as usize + 1, (self.v[x] % 100) / 10);
+                self.write_ram(self.i as usize + 2, self.v[x] % 10);
 
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x5, 0x5) => {
+            Instruction::StoreRegs(x) => {
                 // LD [I], Vx (MEM)
                 // Store registers V0 through Vx in memory starting at location I.
 
                 // The interpreter copies the values of registers V0 through Vx
                 // into memory, starting at the address in I.
 
+                self.check_ram(self.i as usize, x + 1, inst)?;
                 for j in 0..=x {
-                    self.ram[self.i as usize + j] = self.v[j];
+                    self.write_ram(self.i as usize + j, self.v[j]);
                 }
+                self.increment_i(x);
 
                 ProgramCounter::Next
             }
 
-            (0xF, _, 0x6, 0x5) => {
+            Instruction::LoadRegs(x) => {
                 // LD Vx, [I] (MEM)
                 // Read registers V0 through Vx from memory starting at location I.
 
                 // The interpreter reads values from memory starting at location
                 // I into registers V0 through Vx.
 
+                self.check_ram(self.i as usize, x + 1, inst)?;
                 for j in 0..=x {
                     self.v[j] = self.ram[self.i as usize + j];
                 }
+                self.increment_i(x);
 
                 ProgramCounter::Next
             }
-            _ => {
-                println!("Warning: unrecognized instruction: {:04x}", inst);
+
+            Instruction::StoreFlags(x) => {
+                // LD R, Vx (MEM, SUPER-CHIP)
+                // Store V0..VX in RPL user flags.
+
+                // The HP-48 had 8 of these flags, XO-CHIP extends them to all
+                // 16 registers.
+                self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+
+                ProgramCounter::Next
+            }
+
+            Instruction::LoadFlags(x) => {
+                // LD Vx, R (MEM, SUPER-CHIP)
+                // Read V0..VX from RPL user flags.
+
+                self.v[..=x].copy_from_slice(&self.rpl[..=x]);
+
                 ProgramCounter::Next
             }
+            _ => {
+                return Err(C8Error::InvalidOpcode {
+                    pc: self.pc,
+                    opcode: inst,
+                })
+            }
         };
 
         match pc_change {
-            ProgramCounter::Next => self.pc += 2,
-            ProgramCounter::Skip => self.pc += 4,
+            ProgramCounter::Next => self.pc = self.pc.wrapping_add(2),
+            ProgramCounter::Skip => self.pc = self.pc.wrapping_add(self.skip_length()),
             ProgramCounter::Jump(addr) => self.pc = addr,
         }
 
-        println!(
-            "[Executing] | pc: {:#03x} | inst: {:#04x} | i: {:#04x} | v: {:02x?}",
-            self.pc, inst, self.i, self.v
+        let drawn = matches!(
+            instruction,
+            Instruction::Cls
+                | Instruction::Draw(..)
+                | Instruction::ScrollDown(_)
+                | Instruction::ScrollUp(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Low
+                | Instruction::High
+        );
+        self.map_vip_memory(drawn);
+
+        trace!(
+            target: "cpu",
+            "Executing | pc: {:#03x} | inst: {:#04x} | i: {:#04x} | v: {:02x?}",
+            self.pc,
+            inst,
+            self.i,
+            self.v
+        );
+
+        Ok(())
+    }
+}
+
+// Machine Code Methods
+impl C8 {
+    // Runs the CDP1802 subroutine at `addr` until it returns to the
+    // interpreter with SEP R4 (D4), and returns the address the program
+    // continues at. The registers are set up the way the VIP interpreter
+    // left them: R3 is the program counter of the subroutine, R2 the stack
+    // pointer and X, R5 the CHIP-8 program counter, R6 and R7 point to Vx
+    // and Vy, RA is I and RB.1 the page of the display. The subroutine can
+    // change I and the program counter through RA and R5, and with the VIP
+    // memory quirk the registers, the stack and the display in RAM.
+    // Source: RCA COSMAC VIP Instruction Manual (VIP-311), CHIP-8 interpreter listing
+    fn call_machine_code(&mut self, addr: u16, inst: u16) -> Result<u16, C8Error> {
+        let mut cpu = Cdp1802::new();
+        cpu.r[0x2] = (VIP_STACK_END - 1 - 2 * self.stack.len()) as u16;
+        cpu.r[0x3] = addr;
+        cpu.r[0x5] = self.pc.wrapping_add(2);
+        cpu.r[0x6] = (VIP_REGISTERS + ((inst >> 8) & 0xF) as usize) as u16;
+        cpu.r[0x7] = (VIP_REGISTERS + ((inst >> 4) & 0xF) as usize) as u16;
+        cpu.r[0xA] = self.i;
+        cpu.r[0xB] = (VIP_DISPLAY as u16) & 0xFF00;
+        cpu.x = 0x2;
+        cpu.p = 0x3;
+        debug!(target: "cpu", "Calling machine code at {:#05x}", addr);
+
+        let mut cycles = 0;
+        for _ in 0..MACHINE_CODE_LIMIT {
+            cycles += cpu.step(&mut self.ram);
+            if cpu.p == 0x4 {
+                debug!(target: "cpu", "Machine code returned after {} cycles", cycles);
+                self.machine_cycles = cycles;
+                self.i = cpu.r[0xA];
+                if self.quirks.vip_memory {
+                    for addr in VIP_STACK_START..VIP_DISPLAY_END {
+                        self.write_vip_memory(addr, self.ram[addr]);
+                    }
+                }
+                return Ok(cpu.r[0x5]);
+            }
+        }
+
+        Err(C8Error::MachineCodeTimeout {
+            pc: self.pc,
+            opcode: inst,
+        })
+    }
+}
+
+// XO-CHIP Methods
+impl C8 {
+    // Skipping over the 4 byte `LD I, long` has to skip both of its halves.
+    fn skip_length(&self) -> u16 {
+        let next = self.pc as usize + 2;
+        let len = match self.ram.get(next..next + 2) {
+            Some(word) => {
+                Instruction::decode(u16::from_be_bytes([word[0], word[1]])).len(&self.quirks)
+            }
+            None => 2,
+        };
+        2 + len
+    }
+
+    // Registers from x to y inclusive, in descending order if x > y.
+    fn register_range(x: usize, y: usize) -> Vec<usize> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    // The held keys as a mask with bit k set for key k.
+    fn key_mask(key_press: &[bool; 16]) -> u16 {
+        (0..16)
+            .filter(|&k| key_press[k])
+            .fold(0, |mask, k| mask | 1 << k)
+    }
+}
+
+// Display Methods
+impl C8 {
+    /// Width of the display in the current resolution.
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    /// Height of the display in the current resolution.
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
+        }
+    }
+
+    /// Whether the SUPER-CHIP 128x64 high resolution mode is enabled.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Whether the program stopped itself with the SUPER-CHIP `EXIT`.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// The display buffer, row by row. Each pixel holds one bit per
+    /// bitplane, so plain CHIP-8 pixels are either 0 or 1.
+    pub fn pixels(&self) -> &[u8] {
+        &self.display
+    }
+
+    /// The display buffer converted to 0RGB colours through `PALETTE`, ready
+    /// to be drawn by a frontend.
+    pub fn framebuffer(&self) -> Vec<u32> {
+        self.display
+            .iter()
+            .map(|px| PALETTE[*px as usize])
+            .collect()
+    }
+
+    // Switches between 64x32 and 128x64, resizing and clearing the display.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = vec![0; self.width() * self.height()];
+        debug!(
+            target: "display",
+            "Resolution set to {}x{}",
+            self.width(),
+            self.height()
         );
+    }
+
+    // Moves the selected bitplanes `dx` pixels right and `dy` pixels down,
+    // pixels moved in from outside the display are cleared.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.planes;
+        let old = self.display.clone();
+        for cuy in 0..height {
+            for cux in 0..width {
+                let (srcx, srcy) = (cux - dx, cuy - dy);
+                let moved = if srcx >= 0 && srcx < width && srcy >= 0 && srcy < height {
+                    old[(srcy * width + srcx) as usize] & planes
+                } else {
+                    0
+                };
+                let cord = (cuy * width + cux) as usize;
+                self.display[cord] = (old[cord] & !planes) | moved;
+            }
+        }
+    }
+}
+
+// Sound Methods
+impl C8 {
+    /// The XO-CHIP audio pattern, or `None` while the program has not loaded
+    /// one and the buzzer should play a plain tone.
+    pub fn audio_pattern(&self) -> Option<[u8; PATTERN_SIZE]> {
+        self.pattern
+    }
+
+    /// Number of pattern bits played per second, set by the XO-CHIP pitch
+    /// register.
+    pub fn audio_rate(&self) -> f32 {
+        // The playback rate is 4000*2^((vX-64)/48) Hz, so a pitch of 64 plays
+        // 4000 bits per second and every 48 steps double or halve it.
+        // Source: https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+// Timer Methods
+impl C8 {
+    /// Signals the 60 Hz display interrupt: decrements the delay and sound
+    /// timers and releases a `DRW` waiting on the display wait quirk.
+    pub fn tick_timers(&mut self) {
+        // The delay timer is active whenever the delay timer register (DT)
+        // is non-zero. This timer does nothing more than subtract 1 from
+        // the value of DT at a rate of 60Hz. When DT reaches 0, it
+        // deactivates.
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+
+        // The sound timer is active whenever the sound timer register (ST)
+        // is non-zero. This timer also decrements at a rate of 60Hz,
+        // however, as long as ST's value is greater than zero, the Chip-8
+        // buzzer will sound. When ST reaches zero, the sound timer
+        // deactivates.
+        if self.st > 0 {
+            self.st -= 1;
+        }
+
+        self.vblank = true;
+    }
+}
 
-        wait_for_key
+// Frame Methods
+impl C8 {
+    /// Sets how many instructions `run_frame` executes. The speed is a
+    /// setting of the frontend, it is not part of save states.
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.carry = 0;
+        self.frame_left = 0;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Runs one 60 Hz frame: executes the instructions of a frame at the
+    /// configured speed, then ticks the timers. A program that exits ends
+    /// the frame early.
+    pub fn run_frame(&mut self, key_press: &[bool; 16]) -> Result<(), C8Error> {
+        self.run_frame_with(key_press, |_| true).map(|_| ())
+    }
+
+    /// Runs a frame like `run_frame`, calling `before` ahead of every
+    /// instruction. When it returns false the frame stops there without
+    /// ticking the timers and false is returned, so a debugger can stop the
+    /// program between instructions. The next call finishes the frame.
+    pub fn run_frame_with<F>(
+        &mut self,
+        key_press: &[bool; 16],
+        mut before: F,
+    ) -> Result<bool, C8Error>
+    where
+        F: FnMut(&mut C8) -> bool,
+    {
+        if self.frame_left == 0 {
+            self.frame_left = self.frame_budget();
+        }
+        while self.frame_left > 0 {
+            if self.exited {
+                self.frame_left = 0;
+                return Ok(true);
+            }
+            if !before(self) {
+                return Ok(false);
+            }
+            let cost = match self.speed {
+                Speed::Vip => match self.vip_step(key_press)? {
+                    Some(cycles) => cycles,
+                    // The rest of the frame is spent waiting.
+                    None => self.frame_left,
+                },
+                _ => {
+                    self.run(key_press)?;
+                    1
+                }
+            };
+            // An instruction that runs past the end of the frame takes the
+            // cycles it overran from the next one.
+            if let Speed::Vip = self.speed {
+                self.carry = cost.saturating_sub(self.frame_left);
+            }
+            self.frame_left = self.frame_left.saturating_sub(cost);
+        }
+        self.tick_timers();
+
+        Ok(true)
+    }
+
+    // Number of instructions, or VIP machine cycles, the next frame runs. A
+    // rate per second carries the remainder of the division by 60 over to the
+    // next frame, so every second executes exactly that many instructions.
+    fn frame_budget(&mut self) -> u32 {
+        match self.speed {
+            Speed::InstructionsPerFrame(count) => count,
+            Speed::InstructionsPerSecond(rate) => {
+                let total = rate + self.carry;
+                self.carry = total % 60;
+                total / 60
+            }
+            Speed::Vip => {
+                // A frame always gets to run at least one instruction.
+                let cycles = timing::VIP_FRAME_CYCLES.saturating_sub(self.carry).max(1);
+                self.carry = 0;
+                cycles
+            }
+        }
+    }
+
+    // Executes an instruction with VIP timing and returns the machine cycles
+    // it took, or `None` when it waits for the display interrupt: a `DRW`
+    // before the interrupt, or an `LD Vx, K` waiting for a key, which spends
+    // the frame polling the keypad.
+    fn vip_step(&mut self, key_press: &[bool; 16]) -> Result<Option<u32>, C8Error> {
+        if self.key_wait.is_some() {
+            self.run(key_press)?;
+            return Ok(None);
+        }
+        let instruction = self.fetch()?;
+        if let Instruction::Draw(..) = instruction {
+            if !self.vblank {
+                return Ok(None);
+            }
+        }
+
+        let pc = self.pc;
+        self.execute(instruction, key_press)?;
+        let skipped = self.pc != pc.wrapping_add(2);
+        let machine_cycles = std::mem::take(&mut self.machine_cycles);
+        Ok(Some(
+            timing::vip_cycles(instruction, skipped) + machine_cycles,
+        ))
     }
 }
 
+// Random Methods
+impl C8 {
+    /// Reseeds the generator behind `RND Vx, byte`. Machines seeded alike and
+    /// given the same input run identically, otherwise the seed is random.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+}
+
+// State Methods
+impl C8 {
+    /// Saves the whole machine, including its quirks and a pending
+    /// `LD Vx, K`, in a versioned binary format read by `load_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.raw(&STATE_MAGIC);
+        state.u16(STATE_VERSION);
+
+        state.quirks(&self.quirks);
+
+        state.bytes(&self.ram);
+        state.raw(&self.v);
+        state.u16(self.i);
+        state.u8(self.dt);
+        state.u8(self.st);
+        state.u16(self.pc);
+        state.u8(self.stack.len() as u8);
+        for addr in self.stack.iter() {
+            state.u16(*addr);
+        }
+        state.bool(self.key_wait.is_some());
+        state.u8(self.key_wait.map_or(0, |wait| wait.register() as u8));
+        let released = match self.key_wait {
+            Some(KeyWait::Release(_, key)) => Some(key),
+            _ => None,
+        };
+        state.bool(released.is_some());
+        state.u8(released.unwrap_or(0));
+        state.u16(match self.key_wait {
+            Some(KeyWait::Press(_, held)) => held,
+            _ => 0,
+        });
+
+        state.bool(self.hires);
+        state.bytes(&self.display);
+        state.bool(self.vblank);
+        state.bool(self.exited);
+        state.raw(&self.rpl);
+        state.u8(self.planes);
+        state.bool(self.pattern.is_some());
+        state.raw(&self.pattern.unwrap_or([0; PATTERN_SIZE]));
+        state.u8(self.pitch);
+        state.u64(self.rng.state());
+
+        state.finish()
+    }
+
+    /// Restores a machine saved by `save_state`. The machine is left
+    /// unchanged if `state` is not a valid save state.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), C8Error> {
+        let invalid = |reason| Err(C8Error::InvalidState { reason });
+        let mut state = StateReader::new(state);
+        if state.raw(STATE_MAGIC.len())? != STATE_MAGIC {
+            return invalid("not a save state");
+        }
+        if state.u16()? != STATE_VERSION {
+            return invalid("unsupported version");
+        }
+
+        let mut c8 = C8::new(state.quirks()?);
+
+        let ram = state.bytes()?;
+        if ram.len() != c8.ram.len() {
+            return invalid("memory size does not match the quirks");
+        }
+        c8.ram.copy_from_slice(ram);
+        c8.v.copy_from_slice(state.raw(REGISTER_COUNT)?);
+        c8.i = state.u16()?;
+        c8.dt = state.u8()?;
+        c8.st = state.u8()?;
+        c8.pc = state.u16()?;
+        let depth = state.u8()? as usize;
+        if depth > c8.stack_size() {
+            return invalid("stack too deep");
+        }
+        for _ in 0..depth {
+            c8.stack.push(state.u16()?);
+        }
+        let waiting = state.bool()?;
+        let x = state.u8()? as usize;
+        if x >= REGISTER_COUNT {
+            return invalid("unknown register");
+        }
+        let released = state.bool()?;
+        let key = state.u8()?;
+        let held = state.u16()?;
+        if key >= 16 {
+            return invalid("unknown key");
+        }
+        c8.key_wait = match (waiting, released) {
+            (false, _) => None,
+            (true, false) => Some(KeyWait::Press(x, held)),
+            (true, true) => Some(KeyWait::Release(x, key)),
+        };
+
+        c8.set_hires(state.bool()?);
+        let display = state.bytes()?;
+        if display.len() != c8.display.len() {
+            return invalid("display size does not match the resolution");
+        }
+        // A pixel indexes `PALETTE` with its bitplanes.
+        if display.iter().any(|px| *px as usize >= PALETTE.len()) {
+            return invalid("unknown pixel colour");
+        }
+        c8.display.copy_from_slice(display);
+        c8.vblank = state.bool()?;
+        c8.exited = state.bool()?;
+        c8.rpl.copy_from_slice(state.raw(RPL_FLAG_COUNT)?);
+        c8.planes = state.u8()?;
+        if c8.planes as usize >= PALETTE.len() {
+            return invalid("unknown bitplanes");
+        }
+        let has_pattern = state.bool()?;
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern.copy_from_slice(state.raw(PATTERN_SIZE)?);
+        c8.pattern = if has_pattern { Some(pattern) } else { None };
+        c8.pitch = state.u8()?;
+        c8.rng = match Rng::from_state(state.u64()?) {
+            Some(rng) => rng,
+            None => return invalid("invalid random number generator state"),
+        };
+        state.finish()?;
+
+        // The speed is a setting of the frontend and stays, a frame in
+        // progress is abandoned.
+        c8.speed = self.speed;
+        c8.carry = self.carry;
+        *self = c8;
+        Ok(())
+    }
+
+    /// Saves the machine as JSON, with the same version as `save_state`.
+    #[cfg(feature = "json")]
+    pub fn save_state_json(&self) -> String {
+        let state = JsonState {
+            version: STATE_VERSION,
+            machine: self,
+        };
+        // Every field of the machine can be represented in JSON.
+        serde_json::to_string(&state).unwrap()
+    }
+
+    /// Restores a machine saved by `save_state_json`. The machine is left
+    /// unchanged if `state` is not a valid save state.
+    #[cfg(feature = "json")]
+    pub fn load_state_json(&mut self, state: &str) -> Result<(), C8Error> {
+        let invalid = |reason| Err(C8Error::InvalidState { reason });
+        let state: JsonState<C8> = match serde_json::from_str(state) {
+            Ok(state) => state,
+            Err(_) => return invalid("not a save state"),
+        };
+        if state.version != STATE_VERSION {
+            return invalid("unsupported version");
+        }
+        // The binary format checks the machine while reading it, so a state
+        // read from JSON goes through the same checks.
+        self.load_state(&state.machine.save_state())
+    }
+}
+
+// The JSON save state: the version of the format and every field of `C8`.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonState<M> {
+    version: u16,
+    machine: M,
+}
+
 // New
 impl C8 {
-    pub fn new() -> C8 {
+    /// Creates a machine with cleared memory and the program counter at 0x200,
+    /// interpreting ambiguous instructions according to `quirks`.
+    pub fn new(quirks: Quirks) -> C8 {
+        // Machine code finds the registers and the display in VIP memory.
+        let quirks = Quirks {
+            vip_memory: quirks.vip_memory || quirks.machine_code,
+            ..quirks
+        };
         C8 {
-            ram: [0; RAM_SIZE],
+            ram: vec![
+                0;
+                if quirks.xo_chip {
+                    XO_RAM_SIZE
+                } else {
+                    RAM_SIZE
+                }
+            ],
             v: [0; REGISTER_COUNT],
-            display: [PX_OFF; WIDTH * HEIGHT],
+            display: vec![0; WIDTH * HEIGHT],
             i: 0,
             dt: 0,
             st: 0,
             stack: Vec::new(),
             pc: PROGRAM_START as u16,
+            quirks,
+            vblank: false,
+            hires: false,
+            exited: false,
+            rpl: [0; RPL_FLAG_COUNT],
+            planes: 1,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            key_wait: None,
+            rng: Rng::new(rand::random()),
+            writes: None,
+            speed: Speed::default(),
+            carry: 0,
+            frame_left: 0,
+            machine_cycles: 0,
+        }
+    }
+}
+
+impl Default for C8 {
+    fn default() -> C8 {
+        C8::new(Quirks::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_KEYS: [bool; 16] = [false; 16];
+
+    fn machine(rom: &[u8]) -> C8 {
+        let mut c8 = C8::new(Quirks::default());
+        c8.load_ram(rom).unwrap();
+        c8
+    }
+
+    #[test]
+    fn key_skips_use_the_low_nibble_of_vx() {
+        // LD V0, 0x20; SKP V0
+        let mut c8 = machine(&[0x60, 0x20, 0xE0, 0x9E]);
+        c8.run(&NO_KEYS).unwrap();
+        c8.run(&NO_KEYS).unwrap();
+        assert_eq!(c8.pc, 0x204);
+
+        // LD V0, 0x25; SKNP V0, with key 5 held
+        let mut c8 = machine(&[0x60, 0x25, 0xE0, 0xA1]);
+        let mut keys = NO_KEYS;
+        keys[5] = true;
+        c8.run(&keys).unwrap();
+        c8.run(&keys).unwrap();
+        assert_eq!(c8.pc, 0x204);
+    }
+
+    #[test]
+    fn corrupted_states_are_rejected() {
+        let mut c8 = machine(&[0x00, 0xE0]);
+        let original = c8.save_state();
+
+        let mut bad_pixel = machine(&[0x00, 0xE0]);
+        bad_pixel.display[0] = 0x10;
+        assert!(c8.load_state(&bad_pixel.save_state()).is_err());
+
+        let mut bad_planes = machine(&[0x00, 0xE0]);
+        bad_planes.planes = 0x10;
+        assert!(c8.load_state(&bad_planes.save_state()).is_err());
+
+        assert_eq!(c8.save_state(), original);
+    }
+
+    fn steps(c8: &mut C8, count: usize) {
+        for _ in 0..count {
+            c8.run(&NO_KEYS).unwrap();
+        }
+    }
+
+    fn run_with(quirks: Quirks, rom: &[u8], count: usize) -> C8 {
+        let mut c8 = C8::new(quirks);
+        c8.load_ram(rom).unwrap();
+        steps(&mut c8, count);
+        c8
+    }
+
+    #[test]
+    fn shifts_follow_the_quirk() {
+        // LD V0, 0x10; LD V1, 0x03; SHR V0, V1
+        let rom = [0x60, 0x10, 0x61, 0x03, 0x80, 0x16];
+        let on = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        assert_eq!(run_with(on, &rom, 3).v[0], 0x01);
+        assert_eq!(run_with(Quirks::default(), &rom, 3).v[0], 0x08);
+    }
+
+    #[test]
+    fn loads_and_stores_follow_the_quirk() {
+        // LD I, 0x300; LD [I], V2, and the same with LD V2, [I]
+        for rom in &[[0xA3, 0x00, 0xF2, 0x55], [0xA3, 0x00, 0xF2, 0x65]] {
+            for &(load_store, i) in &[
+                (LoadStore::Unchanged, 0x300),
+                (LoadStore::IncrementByX, 0x302),
+                (LoadStore::IncrementByXPlusOne, 0x303),
+            ] {
+                let quirks = Quirks {
+                    load_store,
+                    ..Quirks::default()
+                };
+                assert_eq!(run_with(quirks, rom, 2).i, i, "{:?}", load_store);
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_follow_the_quirk() {
+        // LD V3, 0x10; JP V0, 0x320
+        let rom = [0x63, 0x10, 0xB3, 0x20];
+        let on = Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        };
+        assert_eq!(run_with(on, &rom, 2).pc, 0x330);
+        assert_eq!(run_with(Quirks::default(), &rom, 2).pc, 0x320);
+    }
+
+    #[test]
+    fn logic_follows_the_quirk() {
+        // LD VF, 0x05; OR/AND/XOR V0, V1
+        for op in 1..=3 {
+            let rom = [0x6F, 0x05, 0x80, 0x10 | op];
+            let on = Quirks {
+                logic_resets_vf: true,
+                ..Quirks::default()
+            };
+            assert_eq!(run_with(on, &rom, 2).v[0xF], 0x00);
+            assert_eq!(run_with(Quirks::default(), &rom, 2).v[0xF], 0x05);
+        }
+    }
+
+    #[test]
+    fn sprites_follow_the_quirk() {
+        // LD V0, 62; LD I, 0x206; DRW V0, V1, 1, with a row of 8 pixels.
+        let rom = [0x60, 0x3E, 0xA2, 0x06, 0xD0, 0x11, 0xFF];
+        let lit = |c8: &C8| -> Vec<usize> {
+            (0..c8.display.len())
+                .filter(|&j| c8.display[j] != 0)
+                .collect()
+        };
+        let on = Quirks {
+            clip_sprites: true,
+            ..Quirks::default()
+        };
+        assert_eq!(lit(&run_with(on, &rom, 3)), vec![62, 63]);
+        assert_eq!(
+            lit(&run_with(Quirks::default(), &rom, 3)),
+            vec![0, 1, 2, 3, 4, 5, 62, 63]
+        );
+    }
+
+    #[test]
+    fn xo_chip_planes_are_drawn_and_scrolled_separately() {
+        // PLANE 2; LD I, 0x20A; DRW V0, V1, 1; PLANE 3; DRW V0, V1, 1, with a
+        // pixel for each plane.
+        let rom = [
+            0xF2, 0x01, 0xA2, 0x0A, 0xD0, 0x11, 0xF3, 0x01, 0xD0, 0x11, 0x80, 0x80,
+        ];
+        let mut c8 = run_with(Quirks::xo_chip(), &rom, 3);
+        assert_eq!(c8.display[0], 0b10);
+        steps(&mut c8, 2);
+        assert_eq!(c8.display[0], 0b01);
+        assert_eq!(c8.v[0xF], 1);
+
+        // PLANE 1; SCD 1
+        let mut c8 = C8::new(Quirks::xo_chip());
+        c8.load_ram(&[0xF1, 0x01, 0x00, 0xC1]).unwrap();
+        c8.display[0] = 0b11;
+        steps(&mut c8, 2);
+        assert_eq!(c8.display[0], 0b10);
+        assert_eq!(c8.display[WIDTH], 0b01);
+
+        let mut c8 = machine(&[0xF2, 0x01]);
+        assert!(c8.run(&NO_KEYS).is_err());
+    }
+
+    #[test]
+    fn xo_chip_long_loads_are_skipped_whole() {
+        // LD I, 0x1234
+        let c8 = run_with(Quirks::xo_chip(), &[0xF0, 0x00, 0x12, 0x34], 1);
+        assert_eq!(c8.i, 0x1234);
+        assert_eq!(c8.pc, 0x204);
+
+        // SE V0, 0x00; LD I, 0x1234
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+        assert_eq!(run_with(Quirks::xo_chip(), &rom, 1).pc, 0x206);
+        assert_eq!(run_with(Quirks::default(), &rom, 1).pc, 0x204);
+    }
+
+    #[test]
+    fn xo_chip_register_ranges() {
+        // LD V0, 0x11; LD V1, 0x22; LD V2, 0x33; LD I, 0x300; LD [I], V0-V2;
+        // LD I, 0x310; LD [I], V2-V0; LD V0-V1, [I]
+        let rom = [
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xA3, 0x00, 0x50, 0x22, 0xA3, 0x10, 0x52, 0x02,
+            0x50, 0x13,
+        ];
+        let c8 = run_with(Quirks::xo_chip(), &rom, 8);
+        assert_eq!(c8.ram[0x300..0x303], [0x11, 0x22, 0x33]);
+        assert_eq!(c8.ram[0x310..0x313], [0x33, 0x22, 0x11]);
+        assert_eq!(c8.v[..3], [0x33, 0x22, 0x33]);
+        assert_eq!(c8.i, 0x310);
+    }
+
+    #[test]
+    fn key_waits_store_the_pressed_key() {
+        let mut keys = NO_KEYS;
+        keys[7] = true;
+
+        // LD V0, K
+        let mut c8 = machine(&[0xF0, 0x0A]);
+        steps(&mut c8, 2);
+        assert_eq!(c8.key_wait(), Some(KeyWait::Press(0, 0)));
+        let state = c8.save_state();
+        c8.run(&keys).unwrap();
+        assert_eq!(c8.key_wait(), None);
+        assert_eq!(c8.v[0], 7);
+        assert_eq!(c8.pc, 0x202);
+
+        // The wait survives a save state.
+        c8.load_state(&state).unwrap();
+        assert_eq!(c8.key_wait(), Some(KeyWait::Press(0, 0)));
+    }
+
+    #[test]
+    fn key_waits_follow_the_release_quirk() {
+        let mut keys = NO_KEYS;
+        keys[7] = true;
+        let quirks = Quirks {
+            wait_for_release: true,
+            ..Quirks::default()
+        };
+
+        // LD V0, K
+        let mut c8 = run_with(quirks, &[0xF0, 0x0A], 1);
+        c8.run(&keys).unwrap();
+        assert_eq!(c8.key_wait(), Some(KeyWait::Release(0, 7)));
+        c8.run(&keys).unwrap();
+        assert_eq!(c8.v[0], 0);
+        c8.run(&NO_KEYS).unwrap();
+        assert_eq!(c8.key_wait(), None);
+        assert_eq!(c8.v[0], 7);
+    }
+
+    #[test]
+    fn key_waits_ignore_keys_held_before() {
+        let mut keys = NO_KEYS;
+        keys[3] = true;
+
+        // LD V5, K
+        let mut c8 = machine(&[0xF5, 0x0A]);
+        c8.run(&keys).unwrap();
+        c8.run(&keys).unwrap();
+        assert_eq!(c8.key_wait(), Some(KeyWait::Press(5, 1 << 3)));
+        c8.run(&NO_KEYS).unwrap();
+        assert_eq!(c8.key_wait(), Some(KeyWait::Press(5, 0)));
+        c8.run(&keys).unwrap();
+        assert_eq!(c8.key_wait(), None);
+        assert_eq!(c8.v[5], 3);
+    }
+
+    #[test]
+    fn frames_run_their_instructions_and_tick_the_timers() {
+        // ADD V0, 1; JP 0x200
+        let mut c8 = machine(&[0x70, 0x01, 0x12, 0x00]);
+        c8.dt = 10;
+        c8.set_speed(Speed::InstructionsPerFrame(8));
+        for _ in 0..3 {
+            c8.run_frame(&NO_KEYS).unwrap();
+        }
+        assert_eq!(c8.v[0], 12);
+        assert_eq!(c8.dt, 7);
+    }
+
+    #[test]
+    fn rates_per_second_carry_over_between_frames() {
+        // ADD V0, 1 over and over.
+        let mut c8 = machine(&[0x70, 0x01].repeat(8));
+        c8.set_speed(Speed::InstructionsPerSecond(90));
+        c8.run_frame(&NO_KEYS).unwrap();
+        assert_eq!(c8.v[0], 1);
+        c8.run_frame(&NO_KEYS).unwrap();
+        assert_eq!(c8.v[0], 3);
+    }
+
+    #[test]
+    fn vip_frames_carry_over_overrun_cycles() {
+        // LD B, V0 over and over, 204 cycles each. 13 of them overrun the
+        // frame by 38 cycles, which the next frame is short of.
+        let mut c8 = machine(&[0xF0, 0x33].repeat(40));
+        c8.set_speed(Speed::Vip);
+        c8.run_frame(&NO_KEYS).unwrap();
+        assert_eq!(c8.pc, 0x200 + 13 * 2);
+        assert_eq!(c8.carry, 38);
+        c8.run_frame(&NO_KEYS).unwrap();
+        assert_eq!(c8.pc, 0x200 + 26 * 2);
+        assert_eq!(c8.carry, 76);
+    }
+
+    #[test]
+    fn vip_frames_draw_once_per_interrupt() {
+        // DRW V0, V1, 1 over and over.
+        let mut c8 = machine(&[0xD0, 0x11].repeat(4));
+        c8.set_speed(Speed::Vip);
+        for _ in 0..3 {
+            c8.run_frame(&NO_KEYS).unwrap();
+        }
+        assert_eq!(c8.pc, 0x204);
+    }
+
+    #[test]
+    fn super_chip_scrolls_move_the_display() {
+        // SCD 2; SCR; SCL; SCL
+        let mut c8 = machine(&[0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]);
+        c8.display[0] = 1;
+        steps(&mut c8, 1);
+        assert_eq!(c8.display.iter().position(|&px| px != 0), Some(2 * WIDTH));
+        steps(&mut c8, 1);
+        assert_eq!(
+            c8.display.iter().position(|&px| px != 0),
+            Some(2 * WIDTH + 4)
+        );
+        steps(&mut c8, 1);
+        assert_eq!(c8.display.iter().position(|&px| px != 0), Some(2 * WIDTH));
+
+        // Pixels scrolled past the edge are gone.
+        steps(&mut c8, 1);
+        assert!(c8.display.iter().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn super_chip_resolution_and_exit() {
+        // HIGH; LOW; EXIT
+        let mut c8 = machine(&[0x00, 0xFF, 0x00, 0xFE, 0x00, 0xFD]);
+        steps(&mut c8, 1);
+        assert!(c8.is_hires());
+        assert_eq!(c8.display.len(), HIRES_WIDTH * HIRES_HEIGHT);
+        steps(&mut c8, 1);
+        assert!(!c8.is_hires());
+        assert_eq!(c8.display.len(), WIDTH * HEIGHT);
+        steps(&mut c8, 2);
+        assert!(c8.has_exited());
+        assert_eq!(c8.pc, 0x204);
+    }
+
+    #[test]
+    fn super_chip_draws_16x16_sprites() {
+        // HIGH; LD I, 0x208; DRW V0, V1, 0 twice, then 32 bytes of sprite.
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x08, 0xD0, 0x10, 0xD0, 0x10];
+        rom.extend_from_slice(&[0xFF; 32]);
+        let mut c8 = machine(&rom);
+        steps(&mut c8, 3);
+        let lit: Vec<usize> = (0..c8.display.len())
+            .filter(|&j| c8.display[j] != 0)
+            .collect();
+        assert_eq!(lit.len(), 16 * 16);
+        assert!(lit
+            .iter()
+            .all(|j| j % HIRES_WIDTH < 16 && j / HIRES_WIDTH < 16));
+        assert_eq!(c8.v[0xF], 0);
+
+        steps(&mut c8, 1);
+        assert!(c8.display.iter().all(|&px| px == 0));
+        assert_eq!(c8.v[0xF], 1);
+    }
+
+    #[test]
+    fn super_chip_big_font_and_flags() {
+        // LD V3, 0x17; LD HF, V3
+        let mut c8 = machine(&[0x63, 0x17, 0xF3, 0x30]);
+        steps(&mut c8, 2);
+        assert_eq!(c8.i as usize, BIG_FONT_START + 7 * 10);
+
+        // LD V0, 0x11; LD V1, 0x22; LD V2, 0x33; LD R, V1; LD V0, 0; LD V1, 0;
+        // LD V2, 0; LD V2, R
+        let mut c8 = machine(&[
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00,
+            0xF2, 0x85,
+        ]);
+        steps(&mut c8, 8);
+        assert_eq!(c8.v[..3], [0x11, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn sys_is_ignored_without_machine_code() {
+        // SYS 0x300
+        let mut c8 = machine(&[0x03, 0x00]);
+        c8.run(&NO_KEYS).unwrap();
+        assert_eq!(c8.pc, 0x202);
+    }
+
+    #[test]
+    fn vip_stack_depth_is_checked() {
+        let quirks = Quirks {
+            vip_memory: true,
+            ..Quirks::default()
+        };
+        // CALL 0x200
+        let mut c8 = C8::new(quirks);
+        c8.load_ram(&[0x22, 0x00]).unwrap();
+        for _ in 0..VIP_STACK_SIZE {
+            c8.run(&NO_KEYS).unwrap();
+        }
+        assert!(c8.run(&NO_KEYS).is_err());
+
+        // A state can't hold more return addresses than the stack has room
+        // for either.
+        let state = c8.save_state();
+        c8.stack.push(0x200);
+        assert!(C8::new(quirks).load_state(&c8.save_state()).is_err());
+        assert!(C8::new(quirks).load_state(&state).is_ok());
+    }
+
+    #[test]
+    fn machine_code_sees_the_registers() {
+        let quirks = Quirks {
+            machine_code: true,
+            ..Quirks::default()
+        };
+        let mut c8 = C8::new(quirks);
+        // LD I, 0x300; LD V2, 0x2A; SYS 0x208; JP 0x206, and at 0x208 the
+        // 1802 routine LDN R6; ADI 0x01; STR R6; GLO RA; ADI 0x02; PLO RA;
+        // SEP R4, which increments Vx and adds 2 to I.
+        let rom = [
+            0xA3, 0x00, 0x62, 0x2A, 0x02, 0x08, 0x12, 0x06, 0x06, 0xFC, 0x01, 0x56, 0x8A, 0xFC,
+            0x02, 0xAA, 0xD4,
+        ];
+        c8.load_ram(&rom).unwrap();
+        for _ in 0..3 {
+            c8.run(&NO_KEYS).unwrap();
         }
+        assert_eq!(c8.v[2], 0x2B);
+        assert_eq!(c8.i, 0x302);
+        assert_eq!(c8.pc, 0x206);
     }
 }
@@ -0,0 +1,414 @@
+//! The RCA CDP1802 processor of the COSMAC VIP, which ran the original
+//! CHIP-8 interpreter.
+//!
+//! CHIP-8 programs for the VIP could call subroutines written in 1802 machine
+//! code with `SYS addr` (0NNN). With the machine code quirk `C8` runs them on
+//! this processor, sharing its RAM, until they return to the interpreter with
+//! `SEP R4` (D4).
+//!
+//! Every instruction of the 1802 is implemented. There are no interrupts or
+//! DMA, `IDL` does nothing, the input lines EF1-EF4 are never set and the
+//! input ports read 0.
+
+// Source: RCA CDP1802 User Manual (MPM-201)
+
+/// The registers of a CDP1802.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cdp1802 {
+    /// Scratchpad registers R0-RF.
+    pub r: [u16; 16],
+    /// Designates the register used as the program counter.
+    pub p: u8,
+    /// Designates the register used as the data pointer.
+    pub x: u8,
+    /// Data register, the accumulator.
+    pub d: u8,
+    /// Data flag, the carry of arithmetic and shifts.
+    pub df: bool,
+    /// Holds X and P while an interrupt is serviced.
+    pub t: u8,
+    /// Interrupt enable.
+    pub ie: bool,
+    /// The Q output, which drives the VIP's speaker.
+    pub q: bool,
+}
+
+impl Cdp1802 {
+    /// A processor after reset: every register cleared and interrupts
+    /// enabled.
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            ie: true,
+            ..Cdp1802::default()
+        }
+    }
+
+    /// Executes the instruction at R(P) in `ram` and returns the machine
+    /// cycles it took. Addresses past the end of `ram` wrap around to its
+    /// start.
+    pub fn step(&mut self, ram: &mut [u8]) -> u32 {
+        let opcode = self.fetch(ram);
+        let n = (opcode & 0xF) as usize;
+        let x = self.x as usize;
+
+        // Every instruction takes 2 machine cycles, fetch and execute, the
+        // long branches and skips (CN) 3.
+        match opcode >> 4 {
+            // IDL waits for an interrupt or DMA, which never come here.
+            0x0 if n == 0 => (),
+            // LDN: load via N
+            0x0 => self.d = read(ram, self.r[n]),
+            // INC, DEC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            // Short branches replace the low byte of the program counter with
+            // the byte that follows them, or skip it. The page is the one of
+            // that byte.
+            0x3 => {
+                let p = self.p as usize;
+                if self.condition(n) {
+                    let target = read(ram, self.r[p]);
+                    self.r[p] = (self.r[p] & 0xFF00) | target as u16;
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(1);
+                }
+            }
+            // LDA: load advance
+            0x4 => {
+                self.d = read(ram, self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            // STR: store via N
+            0x5 => write(ram, self.r[n], self.d),
+            0x6 => match n {
+                // IRX: increment R(X)
+                0x0 => self.r[x] = self.r[x].wrapping_add(1),
+                // OUT 1-7 puts M(R(X)) on the bus and increments R(X).
+                0x1..=0x7 => self.r[x] = self.r[x].wrapping_add(1),
+                // 68 is not an 1802 instruction.
+                0x8 => (),
+                // INP 1-7 reads the bus into M(R(X)) and D.
+                _ => {
+                    self.d = 0;
+                    write(ram, self.r[x], self.d);
+                }
+            },
+            0x7 => self.execute_7(n, ram),
+            // GLO, GHI, PLO, PHI
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = (self.r[n] & 0xFF00) | self.d as u16,
+            0xB => self.r[n] = (self.r[n] & 0x00FF) | ((self.d as u16) << 8),
+            0xC => {
+                self.long_branch(n, ram);
+                return 3;
+            }
+            // SEP, SEX
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => self.execute_f(n, ram),
+        }
+        2
+    }
+
+    // Reads the byte at R(P) and advances the program counter past it.
+    fn fetch(&mut self, ram: &[u8]) -> u8 {
+        let p = self.p as usize;
+        let byte = read(ram, self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        byte
+    }
+
+    // Condition of the short branch 3N and the long branch CN. Conditions
+    // 8-F are the opposite of 0-7.
+    fn condition(&self, n: usize) -> bool {
+        let taken = match n & 0x7 {
+            0x0 => true,
+            0x1 => self.q,
+            0x2 => self.d == 0,
+            0x3 => self.df,
+            // EF1-EF4 are never set.
+            _ => false,
+        };
+        taken != (n & 0x8 != 0)
+    }
+
+    // 7N: the register transfer, arithmetic with carry and Q instructions.
+    fn execute_7(&mut self, n: usize, ram: &mut [u8]) {
+        let x = self.x as usize;
+        match n {
+            // RET, DIS: X and P come from M(R(X)), which is incremented.
+            0x0 | 0x1 => {
+                let xp = read(ram, self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = xp >> 4;
+                self.p = xp & 0xF;
+                self.ie = n == 0x0;
+            }
+            // LDXA: load via X and advance
+            0x2 => {
+                self.d = read(ram, self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            // STXD: store via X and decrement
+            0x3 => {
+                write(ram, self.r[x], self.d);
+                self.r[x] = self.r[x].wrapping_sub(1);
+            }
+            // ADC, SDB, SMB
+            0x4 => self.add(read(ram, self.r[x]), self.df),
+            0x5 => self.subtract(read(ram, self.r[x]), self.d, self.df),
+            0x7 => self.subtract(self.d, read(ram, self.r[x]), self.df),
+            // SHRC: shift right with carry
+            0x6 => {
+                let carry = self.d & 1 != 0;
+                self.d = (self.d >> 1) | ((self.df as u8) << 7);
+                self.df = carry;
+            }
+            // SHLC: shift left with carry
+            0xE => {
+                let carry = self.d & 0x80 != 0;
+                self.d = (self.d << 1) | self.df as u8;
+                self.df = carry;
+            }
+            // SAV: save T
+            0x8 => write(ram, self.r[x], self.t),
+            // MARK: save X and P in T and at M(R2), then X = P.
+            0x9 => {
+                self.t = (self.x << 4) | self.p;
+                write(ram, self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // ADCI, SDBI, SMBI
+            0xC => {
+                let byte = self.fetch(ram);
+                self.add(byte, self.df);
+            }
+            0xD => {
+                let byte = self.fetch(ram);
+                self.subtract(byte, self.d, self.df);
+            }
+            _ => {
+                let byte = self.fetch(ram);
+                self.subtract(self.d, byte, self.df);
+            }
+        }
+    }
+
+    // CN: long branches jump to the address in the two bytes that follow
+    // them, long skips skip those two bytes.
+    fn long_branch(&mut self, n: usize, ram: &[u8]) {
+        let p = self.p as usize;
+        match n {
+            // NOP
+            0x4 => (),
+            // LSNQ, LSNZ, LSNF, LSIE, LSQ, LSZ, LSDF
+            0x5..=0x7 | 0xC..=0xF => {
+                let skip = match n {
+                    0x5 => !self.q,
+                    0x6 => self.d != 0,
+                    0x7 => !self.df,
+                    0xC => self.ie,
+                    0xD => self.q,
+                    0xE => self.d == 0,
+                    _ => self.df,
+                };
+                if skip {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            }
+            // LSKP skips unconditionally.
+            0x8 => self.r[p] = self.r[p].wrapping_add(2),
+            // LBR, LBQ, LBZ, LBDF and their opposites.
+            _ => {
+                let high = self.fetch(ram);
+                let low = self.fetch(ram);
+                if self.condition(n) {
+                    self.r[p] = u16::from_be_bytes([high, low]);
+                }
+            }
+        }
+    }
+
+    // FN: the logic and arithmetic instructions. FN with N of 8 or more takes
+    // its operand from the byte that follows instead of M(R(X)).
+    fn execute_f(&mut self, n: usize, ram: &mut [u8]) {
+        let operand = match n {
+            0x6 | 0xE => 0,
+            0x0..=0x7 => read(ram, self.r[self.x as usize]),
+            _ => self.fetch(ram),
+        };
+        match n & 0x7 {
+            // LDX, LDI
+            0x0 => self.d = operand,
+            // OR, ORI, AND, ANI, XOR, XRI
+            0x1 => self.d |= operand,
+            0x2 => self.d &= operand,
+            0x3 => self.d ^= operand,
+            // ADD, ADI
+            0x4 => self.add(operand, false),
+            // SD, SDI: the operand minus D
+            0x5 => self.subtract(operand, self.d, true),
+            // SHR, SHL
+            0x6 if n == 0x6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            0x6 => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            // SM, SMI: D minus the operand
+            _ => self.subtract(self.d, operand, true),
+        }
+    }
+
+    // D = D + value + carry, DF is the carry out.
+    fn add(&mut self, value: u8, carry: bool) {
+        let sum = self.d as u16 + value as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // D = a - b, borrowing when `no_borrow` is clear. DF is set when there is
+    // no borrow out.
+    fn subtract(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let difference = a as i16 - b as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+fn read(ram: &[u8], addr: u16) -> u8 {
+    ram[addr as usize % ram.len()]
+}
+
+fn write(ram: &mut [u8], addr: u16, value: u8) {
+    let len = ram.len();
+    ram[addr as usize % len] = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs `code` from address 0 for `steps` instructions, with D and DF
+    // preset and R(X) pointing at `operand`, which is placed at 0xFF.
+    fn run(code: &[u8], steps: usize, d: u8, df: bool, operand: u8) -> (Cdp1802, Vec<u8>) {
+        let mut ram = vec![0; 0x200];
+        ram[..code.len()].copy_from_slice(code);
+        ram[0xFF] = operand;
+        let mut cpu = Cdp1802::new();
+        cpu.d = d;
+        cpu.df = df;
+        cpu.x = 2;
+        cpu.r[2] = 0xFF;
+        for _ in 0..steps {
+            cpu.step(&mut ram);
+        }
+        (cpu, ram)
+    }
+
+    fn arithmetic(opcode: u8, d: u8, df: bool, operand: u8) -> (u8, bool) {
+        let (cpu, _) = run(&[opcode], 1, d, df, operand);
+        (cpu.d, cpu.df)
+    }
+
+    #[test]
+    fn addition_carries_into_df() {
+        // ADD, ADC
+        assert_eq!(arithmetic(0xF4, 0x80, true, 0x7F), (0xFF, false));
+        assert_eq!(arithmetic(0xF4, 0x80, false, 0x80), (0x00, true));
+        assert_eq!(arithmetic(0x74, 0x80, true, 0x7F), (0x00, true));
+        assert_eq!(arithmetic(0x74, 0x01, false, 0x01), (0x02, false));
+    }
+
+    #[test]
+    fn subtraction_clears_df_on_borrow() {
+        // SD: M(R(X)) - D, SM: D - M(R(X))
+        assert_eq!(arithmetic(0xF5, 0x01, false, 0x03), (0x02, true));
+        assert_eq!(arithmetic(0xF5, 0x03, true, 0x01), (0xFE, false));
+        assert_eq!(arithmetic(0xF7, 0x03, false, 0x01), (0x02, true));
+        assert_eq!(arithmetic(0xF7, 0x01, true, 0x03), (0xFE, false));
+        // SDB, SMB borrow one more when DF is clear.
+        assert_eq!(arithmetic(0x75, 0x01, false, 0x03), (0x01, true));
+        assert_eq!(arithmetic(0x75, 0x01, true, 0x03), (0x02, true));
+        assert_eq!(arithmetic(0x77, 0x03, false, 0x03), (0xFF, false));
+        assert_eq!(arithmetic(0x77, 0x03, true, 0x03), (0x00, true));
+        // SMI with an immediate operand.
+        let (cpu, _) = run(&[0xFF, 0x05], 1, 0x04, true, 0);
+        assert_eq!((cpu.d, cpu.df), (0xFF, false));
+    }
+
+    #[test]
+    fn shifts_through_df() {
+        // SHRC, SHLC rotate through DF, SHR and SHL shift in a zero.
+        assert_eq!(arithmetic(0x76, 0x81, true, 0), (0xC0, true));
+        assert_eq!(arithmetic(0x7E, 0x81, false, 0), (0x02, true));
+        assert_eq!(arithmetic(0xF6, 0x02, true, 0), (0x01, false));
+        assert_eq!(arithmetic(0xFE, 0x80, false, 0), (0x00, true));
+    }
+
+    #[test]
+    fn short_branches_stay_in_the_page_of_their_byte() {
+        // BR at 0x1FE takes its target byte from 0x1FF, in page 0x01.
+        let mut ram = vec![0; 0x300];
+        ram[0x1FE] = 0x30;
+        ram[0x1FF] = 0x40;
+        let mut cpu = Cdp1802::new();
+        cpu.r[0] = 0x1FE;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.r[0], 0x140);
+
+        // BZ not taken skips the target byte. BNZ is taken.
+        let (cpu, _) = run(&[0x32, 0x40], 1, 1, false, 0);
+        assert_eq!(cpu.r[0], 0x02);
+        let (cpu, _) = run(&[0x3A, 0x40], 1, 1, false, 0);
+        assert_eq!(cpu.r[0], 0x40);
+    }
+
+    #[test]
+    fn long_branches_and_skips() {
+        // LBR, and LBZ not taken skips its address.
+        let (cpu, _) = run(&[0xC0, 0x01, 0x23], 1, 0, false, 0);
+        assert_eq!(cpu.r[0], 0x123);
+        let (cpu, _) = run(&[0xC2, 0x01, 0x23], 1, 1, false, 0);
+        assert_eq!(cpu.r[0], 0x03);
+        // LSKP, LSZ taken and LSNZ not taken.
+        let (cpu, _) = run(&[0xC8], 1, 0, false, 0);
+        assert_eq!(cpu.r[0], 0x03);
+        let (cpu, _) = run(&[0xCE], 1, 0, false, 0);
+        assert_eq!(cpu.r[0], 0x03);
+        let (cpu, _) = run(&[0xC6], 1, 0, false, 0);
+        assert_eq!(cpu.r[0], 0x01);
+        // LSDF with DF set, and long instructions take 3 machine cycles.
+        let mut ram = vec![0xCF; 4];
+        let mut cpu = Cdp1802::new();
+        cpu.df = true;
+        assert_eq!(cpu.step(&mut ram), 3);
+        assert_eq!(cpu.r[0], 0x03);
+    }
+
+    #[test]
+    fn mark_and_return() {
+        // MARK saves X and P at M(R2) and sets X to P, RET restores them.
+        let mut ram = vec![0; 0x100];
+        ram[0x00] = 0x79; // MARK
+        ram[0x01] = 0xE2; // SEX R2
+        ram[0x02] = 0x12; // INC R2
+        ram[0x03] = 0x70; // RET
+        let mut cpu = Cdp1802::new();
+        cpu.x = 5;
+        cpu.r[2] = 0x80;
+        cpu.step(&mut ram);
+        assert_eq!((ram[0x80], cpu.x, cpu.r[2]), (0x50, 0, 0x7F));
+        for _ in 0..3 {
+            cpu.step(&mut ram);
+        }
+        assert_eq!((cpu.x, cpu.p, cpu.r[2], cpu.ie), (5, 0, 0x81, true));
+    }
+}
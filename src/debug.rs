@@ -0,0 +1,454 @@
+//! Running a program under the control of a debugger.
+//!
+//! `Debugger` executes instructions like a frontend does, ticking the timers
+//! every `cycles_per_frame` instructions, but stops on breakpoints,
+//! watchpoints and after single steps so the machine can be inspected and
+//! changed in between.
+
+use crate::c8::C8;
+use crate::error::C8Error;
+use crate::instruction::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A value of the machine that watchpoints look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// One of the registers V0-VF.
+    V(usize),
+    I,
+    Dt,
+    St,
+    /// The byte of memory at an address.
+    Memory(u16),
+}
+
+impl Target {
+    /// The current value of the target in `c8`.
+    pub fn value(&self, c8: &C8) -> u16 {
+        match self {
+            Target::V(x) => c8.v[*x] as u16,
+            Target::I => c8.i(),
+            Target::Dt => c8.dt as u16,
+            Target::St => c8.st as u16,
+            Target::Memory(addr) => c8.ram().get(*addr as usize).copied().unwrap_or(0) as u16,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::V(x) => write!(f, "V{:X}", x),
+            Target::I => write!(f, "I"),
+            Target::Dt => write!(f, "DT"),
+            Target::St => write!(f, "ST"),
+            Target::Memory(addr) => write!(f, "[{:#05x}]", addr),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    // V0-VF, I, DT, ST or [addr].
+    fn from_str(text: &str) -> Result<Target, String> {
+        let upper = text.trim().to_uppercase();
+        let target = match upper.as_str() {
+            "I" => Target::I,
+            "DT" => Target::Dt,
+            "ST" => Target::St,
+            _ => {
+                if let Some(addr) = upper.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+                    Target::Memory(parse_number(addr)?)
+                } else {
+                    match upper.strip_prefix('V') {
+                        Some(x) if x.len() == 1 => match usize::from_str_radix(x, 16) {
+                            Ok(x) => Target::V(x),
+                            Err(_) => return Err(format!("Unknown register {}", text)),
+                        },
+                        _ => return Err(format!("Unknown register {}", text)),
+                    }
+                }
+            }
+        };
+        Ok(target)
+    }
+}
+
+/// How a `Watchpoint::Condition` compares its target with its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Two character operators come first so `<=` is not taken for `<`.
+const COMPARES: [(&str, Compare); 6] = [
+    ("==", Compare::Eq),
+    ("!=", Compare::Ne),
+    ("<=", Compare::Le),
+    (">=", Compare::Ge),
+    ("<", Compare::Lt),
+    (">", Compare::Gt),
+];
+
+impl Compare {
+    fn holds(&self, left: u16, right: u16) -> bool {
+        match self {
+            Compare::Eq => left == right,
+            Compare::Ne => left != right,
+            Compare::Lt => left < right,
+            Compare::Le => left <= right,
+            Compare::Gt => left > right,
+            Compare::Ge => left >= right,
+        }
+    }
+}
+
+/// A condition that stops the program after the instruction that caused it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// An instruction wrote to memory between `start` and `end`, inclusive.
+    Write { start: u16, end: u16 },
+    /// An instruction changed the value of the target.
+    Change(Target),
+    /// An instruction made the comparison true. It does not stop again while
+    /// the comparison stays true.
+    Condition(Target, Compare, u16),
+}
+
+impl Watchpoint {
+    // Whether the watchpoint is hit by the instruction that was executed
+    // with the target at `before`.
+    fn hit(&self, c8: &C8, before: u16) -> bool {
+        match self {
+            Watchpoint::Write { start, end } => c8
+                .writes()
+                .iter()
+                .any(|addr| *addr >= *start as usize && *addr <= *end as usize),
+            Watchpoint::Change(target) => target.value(c8) != before,
+            Watchpoint::Condition(target, compare, value) => {
+                !compare.holds(before, *value) && compare.holds(target.value(c8), *value)
+            }
+        }
+    }
+
+    fn before(&self, c8: &C8) -> u16 {
+        match self {
+            Watchpoint::Write { .. } => 0,
+            Watchpoint::Change(target) | Watchpoint::Condition(target, _, _) => target.value(c8),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Write { start, end } if start == end => write!(f, "write {:#05x}", start),
+            Watchpoint::Write { start, end } => write!(f, "write {:#05x} {:#05x}", start, end),
+            Watchpoint::Change(target) => write!(f, "{}", target),
+            Watchpoint::Condition(target, compare, value) => {
+                let operator = COMPARES.iter().find(|(_, c)| c == compare).unwrap().0;
+                write!(f, "{} {} {:#x}", target, operator, value)
+            }
+        }
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    // `write <addr> [end]`, a target such as `V3` or `[0x300]` to watch for
+    // changes, or a condition such as `V3 == 0x10`.
+    fn from_str(text: &str) -> Result<Watchpoint, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if let Some((&"write", addrs)) = words.split_first() {
+            return match addrs {
+                [addr] => {
+                    let addr = parse_number(addr)?;
+                    Ok(Watchpoint::Write {
+                        start: addr,
+                        end: addr,
+                    })
+                }
+                [start, end] => Ok(Watchpoint::Write {
+                    start: parse_number(start)?,
+                    end: parse_number(end)?,
+                }),
+                _ => Err("Usage: write <addr> [end]".to_string()),
+            };
+        }
+
+        for (operator, compare) in COMPARES.iter() {
+            if let Some(at) = text.find(operator) {
+                let target = text[..at].parse()?;
+                let value = parse_number(text[at + operator.len()..].trim())?;
+                return Ok(Watchpoint::Condition(target, *compare, value));
+            }
+        }
+        Ok(Watchpoint::Change(text.parse()?))
+    }
+}
+
+/// A watchpoint that stopped the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    /// Number of the watchpoint, as returned by `Debugger::add_watchpoint`.
+    pub id: usize,
+    /// Address of the last instruction executed.
+    pub pc: u16,
+    /// The instruction that triggered it, None when the timers ticking at
+    /// the end of a frame did.
+    pub instruction: Option<Instruction>,
+}
+
+// Numbers are decimal or hexadecimal with a 0x prefix.
+fn parse_number(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let lower = text.to_lowercase();
+    let parsed = match lower.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => lower.parse(),
+    };
+    parsed.map_err(|_| format!("Not a number: {}", text))
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested steps were executed.
+    Step,
+    /// The program counter reached a breakpoint.
+    Breakpoint,
+    /// An instruction hit a watchpoint.
+    Watchpoint(Hit),
+    /// The subroutine being finished returned.
+    Return,
+    /// The program stopped itself with the SUPER-CHIP `EXIT`.
+    Exited,
+    /// The program faulted, the program counter is left at the fault.
+    Fault(C8Error),
+    /// The instruction limit ran out before anything else happened.
+    Limit,
+}
+
+/// Breakpoints and stepping for a `C8`.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_watchpoint: usize,
+    cycles_per_frame: u64,
+    cycles: u64,
+}
+
+impl Debugger {
+    /// Creates a debugger that ticks the timers after every
+    /// `cycles_per_frame` instructions.
+    pub fn new(cycles_per_frame: u64) -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            next_watchpoint: 1,
+            cycles_per_frame: cycles_per_frame.max(1),
+            cycles: 0,
+        }
+    }
+
+    /// Number of instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Stops before the instruction at `addr` is executed. Returns false if
+    /// there already was a breakpoint there.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Removes the breakpoint at `addr`, returning whether there was one.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Addresses of all breakpoints, in ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stops after any instruction that hits `watchpoint`. Returns the number
+    /// that identifies the watchpoint.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.insert(id, watchpoint);
+        id
+    }
+
+    /// Removes watchpoint `id`, returning whether there was one.
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.watchpoints.remove(&id).is_some()
+    }
+
+    /// All watchpoints with their numbers, in the order they were added.
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> + '_ {
+        self.watchpoints
+            .iter()
+            .map(|(id, watchpoint)| (*id, watchpoint))
+    }
+
+    /// Executes up to `count` instructions, stopping early at breakpoints.
+    pub fn step(&mut self, c8: &mut C8, key_press: &[bool; 16], count: u64) -> Stop {
+        let mut steps = 0;
+        match self.run_until(c8, key_press, count, |_| {
+            steps += 1;
+            steps == count
+        }) {
+            Stop::Return => Stop::Step,
+            stop => stop,
+        }
+    }
+
+    /// Executes one instruction, but runs a called subroutine to its return
+    /// as if it were a single instruction.
+    pub fn next(&mut self, c8: &mut C8, key_press: &[bool; 16], limit: u64) -> Stop {
+        let call = matches!(c8.fetch(), Ok(Instruction::Call(_)));
+        if !call {
+            return self.step(c8, key_press, 1);
+        }
+
+        let depth = c8.stack().len();
+        let after = c8.pc.wrapping_add(2);
+        match self.run_until(c8, key_press, limit, |c8| {
+            c8.stack().len() == depth && c8.pc == after
+        }) {
+            Stop::Return => Stop::Step,
+            stop => stop,
+        }
+    }
+
+    /// Runs until the current subroutine returns. Without a subroutine on
+    /// the stack this is the same as `cont`.
+    pub fn finish(&mut self, c8: &mut C8, key_press: &[bool; 16], limit: u64) -> Stop {
+        let depth = c8.stack().len();
+        self.run_until(c8, key_press, limit, |c8| c8.stack().len() < depth)
+    }
+
+    /// Runs until a breakpoint is reached, the program exits or faults, or
+    /// `limit` instructions were executed.
+    pub fn cont(&mut self, c8: &mut C8, key_press: &[bool; 16], limit: u64) -> Stop {
+        self.run_until(c8, key_press, limit, |_| false)
+    }
+
+    // Executes instructions until `done` holds after one of them, returning
+    // Stop::Return, or until something else stops the program.
+    fn run_until(
+        &mut self,
+        c8: &mut C8,
+        key_press: &[bool; 16],
+        limit: u64,
+        mut done: impl FnMut(&C8) -> bool,
+    ) -> Stop {
+        let watch_writes = self
+            .watchpoints
+            .values()
+            .any(|watchpoint| matches!(watchpoint, Watchpoint::Write { .. }));
+        c8.log_writes(watch_writes);
+
+        for _ in 0..limit {
+            // The instruction may overwrite itself, so it is decoded first.
+            let pc = c8.pc;
+            let instruction = c8.fetch().ok();
+            let before = self.before(c8);
+
+            if let Err(e) = c8.run(key_press) {
+                return Stop::Fault(e);
+            }
+            self.cycles += 1;
+            if c8.has_exited() {
+                return Stop::Exited;
+            }
+            if let Some(id) = self.hit(c8, &before) {
+                return Stop::Watchpoint(Hit {
+                    id,
+                    pc,
+                    instruction,
+                });
+            }
+
+            // 60 Hz
+            if self.cycles.is_multiple_of(self.cycles_per_frame) {
+                let before = self.before(c8);
+                c8.tick_timers();
+                if let Some(id) = self.hit(c8, &before) {
+                    return Stop::Watchpoint(Hit {
+                        id,
+                        pc,
+                        instruction: None,
+                    });
+                }
+            }
+
+            if done(c8) {
+                return Stop::Return;
+            }
+            if self.breakpoints.contains(&c8.pc) {
+                return Stop::Breakpoint;
+            }
+        }
+        Stop::Limit
+    }
+
+    // The values the watchpoints look at, in the order of `watchpoints`.
+    fn before(&self, c8: &C8) -> Vec<u16> {
+        self.watchpoints.values().map(|w| w.before(c8)).collect()
+    }
+
+    // The first watchpoint hit since the values were `before`.
+    fn hit(&self, c8: &C8, before: &[u16]) -> Option<usize> {
+        self.watchpoints
+            .iter()
+            .zip(before.iter())
+            .find(|((_, watchpoint), before)| watchpoint.hit(c8, **before))
+            .map(|((id, _), _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn watchpoints_name_what_hit_them() {
+        let mut c8 = C8::new(Quirks::default());
+        // LD V0, 0x05; LD DT, V0; JP 0x204
+        c8.load_ram(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+        let mut debugger = Debugger::new(3);
+        let id = debugger.add_watchpoint("DT".parse().unwrap());
+        let keys = [false; 16];
+
+        let stop = debugger.cont(&mut c8, &keys, 100);
+        let hit = Hit {
+            id,
+            pc: 0x202,
+            instruction: Some(Instruction::SetDelay(0)),
+        };
+        assert_eq!(stop, Stop::Watchpoint(hit));
+
+        // The timers tick after the third instruction.
+        let stop = debugger.cont(&mut c8, &keys, 100);
+        let hit = Hit {
+            id,
+            pc: 0x204,
+            instruction: None,
+        };
+        assert_eq!(stop, Stop::Watchpoint(hit));
+        assert_eq!(c8.dt, 4);
+    }
+}
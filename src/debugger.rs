@@ -0,0 +1,341 @@
+// A command prompt for debugging a ROM in the terminal, on top of the
+// library's `Debugger`.
+
+use chip8::debug::{Debugger, Stop, Watchpoint};
+use chip8::{Instruction, KeyWait, C8};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+// Instructions `continue`, `next` and `finish` execute before they give up and
+// return to the prompt, so a program that never reaches a breakpoint does not
+// hang the debugger.
+pub const RUN_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+Commands, numbers are decimal or 0x hexadecimal:
+  s, step [n]          execute n instructions (1)
+  n, next              step over CALL
+  finish               run until the current subroutine returns
+  c, continue          run until a breakpoint
+  b, break <addr>      set a breakpoint
+  d, delete <addr>     remove a breakpoint
+  breakpoints          list breakpoints
+  w, watch <expr>      stop when a target changes (V3, I, DT, ST, [addr]),
+                       a condition becomes true (V3 == 0x10) or
+                       memory is written (write <addr> [end])
+  unwatch <n>          remove watchpoint n
+  watchpoints          list watchpoints
+  r, regs              show registers, timers and the stack
+  x <addr> [len]       show memory (16 bytes)
+  l, list [n]          disassemble n instructions from pc (8)
+  set <reg> <value>    change V0-VF, I, PC, DT or ST
+  write <addr> <byte>… change memory
+  press <key>          hold a key of the keypad (0-F)
+  release <key>        release a held key
+  q, quit              leave the debugger
+An empty line repeats the previous command.";
+
+/// Runs the prompt on stdin until `quit` or the end of input.
+pub fn run(mut c8: C8, cycles_per_frame: u64) {
+    let mut debugger = Debugger::new(cycles_per_frame);
+    let mut key_press = [false; 16];
+    let mut previous = String::new();
+
+    println!("CHIP-8 debugger, type help for a list of commands");
+    show_current(&c8);
+
+    let stdin = io::stdin();
+    loop {
+        print!("(chip8) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let line = match line.trim() {
+            "" => previous.clone(),
+            line => line.to_string(),
+        };
+        previous = line.clone();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+        let result = match command {
+            "s" | "step" => count(args.first()).map(|n| {
+                let stop = debugger.step(&mut c8, &key_press, n as u64);
+                report(&c8, &stop);
+            }),
+            "n" | "next" => {
+                let stop = debugger.next(&mut c8, &key_press, RUN_LIMIT);
+                report(&c8, &stop);
+                Ok(())
+            }
+            "finish" => {
+                let stop = debugger.finish(&mut c8, &key_press, RUN_LIMIT);
+                report(&c8, &stop);
+                Ok(())
+            }
+            "c" | "continue" => {
+                let stop = debugger.cont(&mut c8, &key_press, RUN_LIMIT);
+                report(&c8, &stop);
+                Ok(())
+            }
+            "b" | "break" => address(args.first()).map(|addr| {
+                if debugger.add_breakpoint(addr) {
+                    println!("Breakpoint set at {:#05x}", addr);
+                }
+            }),
+            "d" | "delete" => address(args.first()).map(|addr| {
+                if !debugger.remove_breakpoint(addr) {
+                    println!("No breakpoint at {:#05x}", addr);
+                }
+            }),
+            "breakpoints" => {
+                for addr in debugger.breakpoints() {
+                    println!("{:#05x}  {}", addr, disassemble(&c8, addr));
+                }
+                Ok(())
+            }
+            "w" | "watch" => args.join(" ").parse::<Watchpoint>().map(|watchpoint| {
+                let id = debugger.add_watchpoint(watchpoint);
+                println!("Watchpoint {}: {}", id, watchpoint);
+            }),
+            "unwatch" => watchpoint_id(args.first()).map(|id| {
+                if !debugger.remove_watchpoint(id) {
+                    println!("No watchpoint {}", id);
+                }
+            }),
+            "watchpoints" => {
+                for (id, watchpoint) in debugger.watchpoints() {
+                    println!("{:>3}  {}", id, watchpoint);
+                }
+                Ok(())
+            }
+            "r" | "regs" => {
+                show_registers(&c8);
+                Ok(())
+            }
+            "x" => show_memory(&c8, args),
+            "l" | "list" => optional_number(args, 8).map(|n| {
+                let mut addr = c8.pc;
+                for _ in 0..n {
+                    let marker = if addr == c8.pc { "=>" } else { "  " };
+                    println!("{} {:#05x}  {}", marker, addr, disassemble(&c8, addr));
+                    addr = addr.wrapping_add(instruction_length(&c8, addr));
+                }
+            }),
+            "set" => set_register(&mut c8, args),
+            "write" => write_memory(&mut c8, args),
+            "press" | "release" => key(args.first()).map(|k| {
+                key_press[k] = command == "press";
+            }),
+            "h" | "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "q" | "quit" => break,
+            _ => Err(format!(
+                "Unknown command: {}, type help for a list",
+                command
+            )),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+}
+
+fn report(c8: &C8, stop: &Stop) {
+    if let Some(reason) = describe(c8, stop) {
+        println!("{}", reason);
+    }
+    show_current(c8);
+}
+
+/// Why the program stopped, nothing when it did what was asked.
+pub fn describe(c8: &C8, stop: &Stop) -> Option<String> {
+    let reason = match stop {
+        Stop::Step | Stop::Return => return None,
+        Stop::Breakpoint => format!("Breakpoint at {:#05x}", c8.pc),
+        Stop::Watchpoint(hit) => match hit.instruction {
+            Some(instruction) => format!(
+                "Watchpoint {} hit by {:#05x}  {}",
+                hit.id,
+                hit.pc,
+                instruction.format_with(&c8.quirks, &|_| None)
+            ),
+            None => format!("Watchpoint {} hit by the timer tick", hit.id),
+        },
+        Stop::Exited => "The program exited".to_string(),
+        Stop::Fault(e) => format!("Error: {}", e),
+        Stop::Limit => format!("Stopped after {} instructions", RUN_LIMIT),
+    };
+    Some(reason)
+}
+
+fn show_current(c8: &C8) {
+    println!("=> {:#05x}  {}", c8.pc, disassemble(c8, c8.pc));
+}
+
+fn show_registers(c8: &C8) {
+    for (j, row) in c8.v.chunks(8).enumerate() {
+        let regs: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(k, v)| format!("V{:X}: {:#04x}", j * 8 + k, v))
+            .collect();
+        println!("{}", regs.join("  "));
+    }
+    println!(
+        "PC: {:#05x}  I: {:#05x}  DT: {}  ST: {}",
+        c8.pc,
+        c8.i(),
+        c8.dt,
+        c8.st
+    );
+    let stack: Vec<String> = c8.stack().iter().map(|a| format!("{:#05x}", a)).collect();
+    println!("Stack: [{}]", stack.join(", "));
+    if let Some(wait) = c8.key_wait() {
+        println!("{}", describe_key_wait(wait));
+    }
+}
+
+fn show_memory(c8: &C8, args: &[&str]) -> Result<(), String> {
+    let start = address(args.first())? as usize;
+    let len = optional_number(&args[1..], 16)?;
+    let ram = c8.ram();
+    let end = start.saturating_add(len).min(ram.len());
+    for row in (start..end).step_by(16) {
+        let bytes: Vec<String> = ram[row..(row + 16).min(end)]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        println!("{:#06x}: {}", row, bytes.join(" "));
+    }
+    Ok(())
+}
+
+fn set_register(c8: &mut C8, args: &[&str]) -> Result<(), String> {
+    let (register, value) = match args {
+        [register, value] => (register.to_uppercase(), number(value)?),
+        _ => return Err("Usage: set <reg> <value>".to_string()),
+    };
+    let byte = || u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", value));
+    let word = || u16::try_from(value).map_err(|_| format!("{} does not fit in 16 bits", value));
+    match register.as_str() {
+        "I" => c8.set_i(word()?),
+        "PC" => c8.pc = word()?,
+        "DT" => c8.dt = byte()?,
+        "ST" => c8.st = byte()?,
+        _ => match register.strip_prefix('V') {
+            Some(x) if x.len() == 1 => {
+                let x = usize::from_str_radix(x, 16)
+                    .map_err(|_| format!("Unknown register {}", register))?;
+                c8.v[x] = byte()?;
+            }
+            _ => return Err(format!("Unknown register {}", register)),
+        },
+    }
+    Ok(())
+}
+
+fn write_memory(c8: &mut C8, args: &[&str]) -> Result<(), String> {
+    let start = address(args.first())? as usize;
+    let bytes = args[1..]
+        .iter()
+        .map(|b| {
+            number(b)
+                .and_then(|b| u8::try_from(b).map_err(|_| format!("{} does not fit in a byte", b)))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    let ram = c8.ram_mut();
+    if start + bytes.len() > ram.len() {
+        return Err("Write past the end of memory".to_string());
+    }
+    ram[start..start + bytes.len()].copy_from_slice(&bytes);
+    Ok(())
+}
+
+/// What a pending `LD Vx, K` waits for.
+pub fn describe_key_wait(wait: KeyWait) -> String {
+    match wait {
+        KeyWait::Press(x, _) => format!("Waiting for a key for V{:X}", x),
+        KeyWait::Release(x, key) => {
+            format!("Waiting for key {:X} to be released for V{:X}", key, x)
+        }
+    }
+}
+
+/// The instruction at `addr`, with the address of an XO-CHIP long load.
+pub fn disassemble(c8: &C8, addr: u16) -> String {
+    let ram = c8.ram();
+    let word = |addr: usize| {
+        ram.get(addr..addr + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    match word(addr as usize).map(Instruction::decode) {
+        Some(Instruction::LoadILong) if c8.quirks.xo_chip => match word(addr as usize + 2) {
+            Some(long) => format!("LD I, LONG {:#06x}", long),
+            None => "LD I, LONG".to_string(),
+        },
+        Some(instruction) => instruction.format_with(&c8.quirks, &|_| None),
+        None => "(outside memory)".to_string(),
+    }
+}
+
+/// Length in bytes of the instruction at `addr`.
+pub fn instruction_length(c8: &C8, addr: u16) -> u16 {
+    match c8.ram().get(addr as usize..addr as usize + 2) {
+        Some(word) => Instruction::decode(u16::from_be_bytes([word[0], word[1]])).len(&c8.quirks),
+        None => 2,
+    }
+}
+
+fn number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Not a number: {}", text))
+}
+
+fn optional_number(args: &[&str], default: usize) -> Result<usize, String> {
+    match args.first() {
+        Some(text) => number(text),
+        None => Ok(default),
+    }
+}
+
+// Number of instructions to step, 1 when not given.
+fn count(arg: Option<&&str>) -> Result<usize, String> {
+    match arg {
+        Some(text) => match number(text)? {
+            0 => Err("The count must be at least 1".to_string()),
+            count => Ok(count),
+        },
+        None => Ok(1),
+    }
+}
+
+fn watchpoint_id(arg: Option<&&str>) -> Result<usize, String> {
+    let text = arg.ok_or("Missing watchpoint number")?;
+    number(text)
+}
+
+fn address(arg: Option<&&str>) -> Result<u16, String> {
+    let text = arg.ok_or("Missing address")?;
+    let addr = number(text)?;
+    u16::try_from(addr).map_err(|_| format!("{} is not an address", text))
+}
+
+fn key(arg: Option<&&str>) -> Result<usize, String> {
+    let text = arg.ok_or("Missing key")?;
+    match usize::from_str_radix(text, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("Not a key of the keypad: {}", text)),
+    }
+}
@@ -0,0 +1,277 @@
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// Programs are loaded at 0x200, so that is where the disassembly starts.
+const PROGRAM_START: usize = 0x200;
+// Number of bytes shown per `db` line of data.
+const DATA_PER_LINE: usize = 8;
+
+/// One line of a disassembly listing: an instruction or a run of data bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Address of the first byte.
+    pub addr: u16,
+    /// The bytes the line was decoded from.
+    pub bytes: Vec<u8>,
+    /// Label defined at this address, if anything refers to it.
+    pub label: Option<String>,
+    /// The instruction in Cowgod's mnemonics, or a `db` directive for data.
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    // Lines are printed in the syntax the assembler reads, with the address
+    // and raw bytes in a comment.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "    {:<24}; {:#05x}: {}",
+            self.text,
+            self.addr,
+            hex.join(" ")
+        )
+    }
+}
+
+// How an instruction passes control on, used to tell code from data.
+enum Flow {
+    Next,
+    // Conditional skips continue at either of the next two instructions.
+    Skip,
+    Jump(u16),
+    Call(u16),
+    // RET, EXIT, computed jumps and instructions this interpreter does not
+    // run: the disassembler cannot follow these.
+    Stop,
+}
+
+struct Decoded {
+    instruction: Instruction,
+    // The address that follows `LD I, LONG` in the next word.
+    long: Option<u16>,
+    flow: Flow,
+    len: usize,
+}
+
+/// Disassembles `rom`, loaded at 0x200, into a listing.
+///
+/// Code is told from data by following every path through the program from
+/// its entry point: bytes that are reached are decoded as instructions, the
+/// rest are emitted as `db` data. Jump and call targets get `L` labels and
+/// addresses loaded into I get `D` labels. `quirks` selects which instruction
+/// set extensions are decoded.
+pub fn disassemble(rom: &[u8], quirks: &Quirks) -> Vec<Line> {
+    let mut code = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+
+    let mut pending = vec![PROGRAM_START];
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        let decoded = match decode_at(rom, addr, quirks) {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+
+        // Jump and call targets get `L` labels, even where data is loaded
+        // from as well.
+        for (target, kind) in targets(&decoded, quirks) {
+            let label = labels.entry(target as usize).or_insert(kind);
+            if kind == 'L' {
+                *label = 'L';
+            }
+        }
+
+        let next = addr + decoded.len;
+        match decoded.flow {
+            Flow::Next => pending.push(next),
+            Flow::Skip => {
+                pending.push(next);
+                pending.push(next + skip_length(rom, next, quirks));
+            }
+            Flow::Jump(target) => pending.push(target as usize),
+            Flow::Call(target) => {
+                pending.push(target as usize);
+                pending.push(next);
+            }
+            Flow::Stop => (),
+        }
+        code.insert(addr, decoded);
+    }
+
+    // Only labels that start a line can be defined, anything else is shown as
+    // a plain address.
+    let mut starts = BTreeSet::new();
+    let mut lines = Vec::new();
+    let end = PROGRAM_START + rom.len();
+    let mut addr = PROGRAM_START;
+    while addr < end {
+        starts.insert(addr);
+        let len = match code.get(&addr) {
+            Some(decoded) => decoded.len,
+            None => {
+                let mut len = 1;
+                while addr + len < end
+                    && len < DATA_PER_LINE
+                    && !code.contains_key(&(addr + len))
+                    && !labels.contains_key(&(addr + len))
+                {
+                    len += 1;
+                }
+                len
+            }
+        };
+        lines.push((addr, len.min(end - addr)));
+        addr += len;
+    }
+    labels.retain(|addr, _| starts.contains(addr));
+
+    let label = |addr: u16| {
+        labels
+            .get(&(addr as usize))
+            .map(|kind| format!("{}{:03X}", kind, addr))
+    };
+
+    lines
+        .into_iter()
+        .map(|(addr, len)| {
+            let bytes = rom[addr - PROGRAM_START..addr - PROGRAM_START + len].to_vec();
+            let text = match code.get(&addr) {
+                Some(decoded) => {
+                    let text = decoded.instruction.format_with(quirks, &label);
+                    match decoded.long {
+                        Some(long) => {
+                            let name = label(long).unwrap_or_else(|| format!("0x{:03X}", long));
+                            format!("{} {}", text, name)
+                        }
+                        None => text,
+                    }
+                }
+                None => {
+                    let data: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                    format!("db {}", data.join(", "))
+                }
+            };
+            Line {
+                addr: addr as u16,
+                bytes,
+                label: label(addr as u16),
+                text,
+            }
+        })
+        .collect()
+}
+
+/// Disassembles `rom` into the text of a listing, one line per instruction.
+pub fn listing(rom: &[u8], quirks: &Quirks) -> String {
+    let mut text = String::new();
+    for line in disassemble(rom, quirks) {
+        text.push_str(&line.to_string());
+        text.push('\n');
+    }
+    text
+}
+
+// Addresses the instruction refers to: `L` for code and `D` for data.
+fn targets(decoded: &Decoded, quirks: &Quirks) -> Vec<(u16, char)> {
+    use Instruction::*;
+
+    match decoded.instruction {
+        // The machine code is not CHIP-8, it is labelled as data.
+        Sys(nnn) if quirks.machine_code => vec![(nnn, 'D')],
+        Sys(nnn) | Jump(nnn) | Call(nnn) | JumpOffset(nnn) => vec![(nnn, 'L')],
+        LoadI(nnn) => vec![(nnn, 'D')],
+        _ => decoded.long.map(|long| (long, 'D')).into_iter().collect(),
+    }
+}
+
+// Conditional skips step over both halves of the 4 byte XO-CHIP `LD I, LONG`.
+fn skip_length(rom: &[u8], addr: usize, quirks: &Quirks) -> usize {
+    fetch(rom, addr).map_or(2, |word| Instruction::decode(word).len(quirks) as usize)
+}
+
+// All instructions are 2 bytes long and are stored
+// most-significant-byte first.
+// Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+fn fetch(rom: &[u8], addr: usize) -> Option<u16> {
+    let offset = addr.checked_sub(PROGRAM_START)?;
+    let bytes = rom.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Decodes the instruction at `addr` with `Instruction::decode`, the decoder
+// `C8::run` executes, leaving out extensions `quirks` does not enable.
+fn decode_at(rom: &[u8], addr: usize, quirks: &Quirks) -> Option<Decoded> {
+    use Instruction::*;
+
+    let instruction = Instruction::decode(fetch(rom, addr)?);
+    if instruction.is_xo_chip() && !quirks.xo_chip {
+        return None;
+    }
+    let flow = match instruction {
+        Unknown(_) => return None,
+        Ret | Exit | JumpOffset(_) => Flow::Stop,
+        Jump(nnn) if nnn as usize == addr => Flow::Stop,
+        Jump(nnn) => Flow::Jump(nnn),
+        Call(nnn) => Flow::Call(nnn),
+        SkipEqByte(..) | SkipNeByte(..) | SkipEqReg(..) | SkipNeReg(..) => Flow::Skip,
+        SkipKey(_) | SkipNotKey(_) => Flow::Skip,
+        _ => Flow::Next,
+    };
+    let long = match instruction {
+        LoadILong => Some(fetch(rom, addr + 2)?),
+        _ => None,
+    };
+    Some(Decoded {
+        instruction,
+        long,
+        flow,
+        len: instruction.len(quirks) as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(rom: &[u8], quirks: &Quirks) -> Vec<String> {
+        disassemble(rom, quirks)
+            .into_iter()
+            .map(|line| line.text)
+            .collect()
+    }
+
+    #[test]
+    fn computed_jumps_follow_the_quirk() {
+        let bxnn = Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        };
+        assert_eq!(texts(&[0xB3, 0x42], &bxnn), ["JP V3, 0x342"]);
+        assert_eq!(texts(&[0xB3, 0x42], &Quirks::default()), ["JP V0, 0x342"]);
+    }
+
+    #[test]
+    fn labels_code_and_data() {
+        // LD I, LONG 0x206; JP 0x200; db 0xFF, 0x00
+        let rom = [0xF0, 0x00, 0x02, 0x06, 0x12, 0x00, 0xFF, 0x00];
+        let lines = disassemble(&rom, &Quirks::xo_chip());
+        assert_eq!(lines[0].label.as_deref(), Some("L200"));
+        assert_eq!(lines[0].text, "LD I, LONG D206");
+        assert_eq!(lines[1].text, "JP L200");
+        assert_eq!(lines[2].label.as_deref(), Some("D206"));
+        assert_eq!(lines[2].text, "db 0xFF, 0x00");
+        // Without XO-CHIP, F000 is not an instruction.
+        assert_eq!(
+            texts(&rom, &Quirks::default())[0],
+            "db 0xF0, 0x00, 0x02, 0x06, 0x12, 0x00, 0xFF, 0x00"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::{error, fmt};
+
+/// Errors raised by the interpreter while loading or running a program.
+///
+/// Faults raised by `C8::run` carry the program counter and the opcode of the
+/// instruction that caused them. The program counter is left pointing at the
+/// faulting instruction, so a host can report the error and decide whether to
+/// reset, patch memory or carry on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum C8Error {
+    /// RET executed with no subroutine on the stack.
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// CALL executed with every stack level in use.
+    StackOverflow { pc: u16, opcode: u16 },
+    /// An instruction (or its fetch, in which case `opcode` is 0) touched memory
+    /// past the end of RAM.
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+    /// The opcode does not decode to any known instruction.
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// The machine code called by `SYS addr` did not return.
+    MachineCodeTimeout { pc: u16, opcode: u16 },
+    /// The ROM does not fit between the program start and the end of RAM.
+    RomTooLarge { size: usize, max: usize },
+    /// A save state passed to `C8::load_state` is corrupt, truncated or from
+    /// an unsupported version.
+    InvalidState { reason: &'static str },
+}
+
+impl fmt::Display for C8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            C8Error::StackUnderflow { pc, opcode } => write!(
+                f,
+                "Stack underflow: cannot return from subroutine (pc: {:#05x}, inst: {:#06x})",
+                pc, opcode
+            ),
+            C8Error::StackOverflow { pc, opcode } => write!(
+                f,
+                "Stack overflow: too many nested subroutines (pc: {:#05x}, inst: {:#06x})",
+                pc, opcode
+            ),
+            C8Error::MemoryOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "Memory access out of bounds at {:#05x} (pc: {:#05x}, inst: {:#06x})",
+                addr, pc, opcode
+            ),
+            C8Error::InvalidOpcode { pc, opcode } => write!(
+                f,
+                "Unrecognized instruction (pc: {:#05x}, inst: {:#06x})",
+                pc, opcode
+            ),
+            C8Error::MachineCodeTimeout { pc, opcode } => write!(
+                f,
+                "Machine code subroutine did not return (pc: {:#05x}, inst: {:#06x})",
+                pc, opcode
+            ),
+            C8Error::RomTooLarge { size, max } => write!(
+                f,
+                "Out of memory: Program too large ({} bytes, at most {} fit)",
+                size, max
+            ),
+            C8Error::InvalidState { reason } => write!(f, "Invalid save state: {}", reason),
+        }
+    }
+}
+
+impl error::Error for C8Error {}
@@ -0,0 +1,127 @@
+use chip8::{C8, PATTERN_SIZE};
+use rodio::Source;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const SAMPLE_RATE: u32 = 48000;
+// Keep the buzzer at a comfortable volume, a full scale square wave is loud.
+const VOLUME: f32 = 0.25;
+// The audio thread copies the shared voice once for every block of this many
+// samples instead of locking it for every sample, so changes are heard within
+// 10 ms.
+const BLOCK_SAMPLES: usize = SAMPLE_RATE as usize / 100;
+
+// What the buzzer should currently play, shared between the emulation loop
+// and the audio thread.
+#[derive(Clone, Copy)]
+struct Voice {
+    pattern: Option<[u8; PATTERN_SIZE]>,
+    rate: f32,
+}
+
+/// The sound of the CHIP-8 buzzer. While the program has not loaded an
+/// XO-CHIP audio pattern this is a plain sine wave at the configured tone,
+/// afterwards the 128 bits of the pattern are played in a loop at the rate set
+/// by the pitch register.
+pub struct Buzzer {
+    voice: Arc<Mutex<Voice>>,
+    // Copy of the shared voice played in the current block.
+    playing: Voice,
+    // Samples left in the current block.
+    block_left: usize,
+    tone: f32,
+    // Position in the tone, in periods, or in the pattern, in bits.
+    phase: f32,
+}
+
+/// Handle used by the emulation loop to pass pattern and pitch changes to a
+/// playing `Buzzer`.
+#[derive(Clone)]
+pub struct BuzzerControl {
+    voice: Arc<Mutex<Voice>>,
+}
+
+impl Buzzer {
+    /// Creates a buzzer that falls back to a sine wave of `tone` Hz.
+    pub fn new(tone: f32) -> (Buzzer, BuzzerControl) {
+        let playing = Voice {
+            pattern: None,
+            rate: 0.0,
+        };
+        let voice = Arc::new(Mutex::new(playing));
+        let control = BuzzerControl {
+            voice: voice.clone(),
+        };
+
+        (
+            Buzzer {
+                voice,
+                playing,
+                block_left: 0,
+                tone,
+                phase: 0.0,
+            },
+            control,
+        )
+    }
+}
+
+impl BuzzerControl {
+    /// Picks up the audio pattern and pitch of `c8`.
+    pub fn update(&self, c8: &C8) {
+        let mut voice = self.voice.lock().unwrap();
+        voice.pattern = c8.audio_pattern();
+        voice.rate = c8.audio_rate();
+    }
+}
+
+impl Iterator for Buzzer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.block_left == 0 {
+            self.playing = *self.voice.lock().unwrap();
+            self.block_left = BLOCK_SAMPLES;
+        }
+        self.block_left -= 1;
+
+        let voice = self.playing;
+        let sample = match voice.pattern {
+            Some(pattern) => {
+                let bits = PATTERN_SIZE * 8;
+                let bit = self.phase as usize % bits;
+                self.phase = (self.phase + voice.rate / SAMPLE_RATE as f32) % bits as f32;
+                if (pattern[bit / 8] >> (7 - bit % 8)) & 0b1 == 1 {
+                    VOLUME
+                } else {
+                    -VOLUME
+                }
+            }
+            None => {
+                let value = (2.0 * std::f32::consts::PI * self.phase).sin();
+                self.phase = (self.phase + self.tone / SAMPLE_RATE as f32) % 1.0;
+                value * VOLUME
+            }
+        };
+
+        Some(sample)
+    }
+}
+
+impl Source for Buzzer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,6 @@
+// Pieces of the minifb/rodio frontend that are not part of the interpreter
+// library.
+
+pub mod audio;
+pub mod rewind;
+pub mod window;
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+
+// Upper bound on the memory held by the deltas, whatever the configured depth.
+// XO-CHIP programs that rewrite large parts of their 64 KiB every frame would
+// otherwise take a lot of it.
+const MAX_BYTES: usize = 64 * 1024 * 1024;
+// Runs of changed bytes closer together than this are stored as one run, the
+// bytes in between are cheaper than the bookkeeping of another run.
+const MERGE_GAP: usize = 8;
+
+// The changes that turn a save state back into the one saved a frame before
+// it.
+struct Delta {
+    // Length of the older state.
+    len: usize,
+    // Offsets and bytes of the older state where it differs from the newer.
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    fn new(older: &[u8], newer: &[u8]) -> Delta {
+        // When the size changed, for example when a SUPER-CHIP program
+        // switched resolution, the older state is kept whole.
+        if older.len() != newer.len() {
+            return Delta {
+                len: older.len(),
+                runs: vec![(0, older.to_vec())],
+            };
+        }
+
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut j = 0;
+        while j < older.len() {
+            if older[j] == newer[j] {
+                j += 1;
+                continue;
+            }
+            // The run ends after the last difference that is followed by
+            // MERGE_GAP equal bytes.
+            let start = j;
+            let mut end = j + 1;
+            let mut k = end;
+            while k < older.len() && k - end < MERGE_GAP {
+                if older[k] != newer[k] {
+                    end = k + 1;
+                }
+                k += 1;
+            }
+            runs.push((start, older[start..end].to_vec()));
+            j = end;
+        }
+
+        Delta {
+            len: older.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, state: &mut Vec<u8>) {
+        state.resize(self.len, 0);
+        for (offset, bytes) in self.runs.iter() {
+            state[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.runs.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+}
+
+/// The recent history of the machine, one save state per frame, stored as the
+/// differences between consecutive frames so only the latest is kept whole.
+pub struct Rewind {
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+    // Number of frames kept.
+    depth: usize,
+    // Bytes held by the deltas.
+    bytes: usize,
+}
+
+impl Rewind {
+    /// Creates a history that goes back `seconds` seconds of 60 Hz frames. A
+    /// depth of 0 disables rewinding.
+    pub fn new(seconds: f64) -> Rewind {
+        Rewind {
+            current: None,
+            deltas: VecDeque::new(),
+            depth: (seconds.max(0.0) * 60.0) as usize,
+            bytes: 0,
+        }
+    }
+
+    /// Whether the history keeps any frames at all.
+    pub fn is_enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// Records the save state of the latest frame, forgetting the oldest
+    /// frames once the history is full.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.depth == 0 {
+            return;
+        }
+        if let Some(older) = self.current.take() {
+            let delta = Delta::new(&older, &state);
+            self.bytes += delta.size();
+            self.deltas.push_back(delta);
+        }
+        self.current = Some(state);
+
+        while self.deltas.len() > self.depth || self.bytes > MAX_BYTES {
+            match self.deltas.pop_front() {
+                Some(delta) => self.bytes -= delta.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Steps one frame back, returning the save state of that frame, or `None`
+    /// once the oldest frame kept has been reached.
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        self.bytes -= delta.size();
+        let current = self.current.as_mut()?;
+        delta.apply(current);
+        Some(current)
+    }
+}
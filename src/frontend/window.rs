@@ -0,0 +1,195 @@
+use super::audio::Buzzer;
+use super::rewind::Rewind;
+use crate::session::Session;
+use crate::Settings;
+use chip8::timing::{Scheduler, FRAME};
+use chip8::{C8, HEIGHT, WIDTH};
+use log::info;
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use rodio::Sink;
+use std::fs;
+
+// Save states are kept in ten numbered slots, files next to the ROM. F1-F10
+// load slots 1-10, holding Shift saves them instead.
+const SLOT_KEYS: [Key; 10] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+];
+
+// Held down to run the program backwards.
+const REWIND_KEY: Key = Key::Backspace;
+
+/// Plays the program loaded into `c8` in a window until it is closed, Escape
+/// is pressed or the program exits.
+pub fn run(mut c8: C8, file_name: &str, settings: Settings) {
+    // Setup Audio
+    let audio_device = rodio::default_output_device().unwrap();
+    let audio_sink = Sink::new(&audio_device);
+    let (audio_source, buzzer) = Buzzer::new(settings.tone);
+    audio_sink.append(audio_source);
+    audio_sink.pause();
+
+    // Setup Window
+    let mut window = Window::new(
+        &format!("CHIP-8: {}", file_name),
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            scale: Scale::X16,
+            ..WindowOptions::default()
+        },
+    )
+    .expect("Unable to open a Window");
+
+    // Using limit_update_rate minifb will check how much time has passed since
+    // the last time and if it's less than the selected time it will sleep for
+    // the remainder of it. This means that if more time has spent than the set
+    // time (external code taking longer) minifb will not do any waiting at all
+    // so there is no loss in CPU performance with this feature.
+    // Source: https://docs.rs/minifb/0.16.0/minifb/struct.Window.html#method.limit_update_rate
+    // The program itself is paced by the scheduler, which runs as many frames
+    // as are due at every display update.
+    window.limit_update_rate(Some(FRAME));
+
+    let mut scheduler = Scheduler::new();
+    let mut rewind = Rewind::new(settings.rewind_seconds);
+    let mut session = Session::new(settings.movie, settings.gdb);
+
+    'frames: while window.is_open() && !window.is_key_down(Key::Escape) && !c8.has_exited() {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        for (j, key) in SLOT_KEYS.iter().enumerate() {
+            if !window.is_key_pressed(*key, KeyRepeat::No) {
+                continue;
+            }
+            let path = slot_path(file_name, j + 1);
+            if shift {
+                match fs::write(&path, c8.save_state()) {
+                    Ok(()) => println!("Saved state to {}", path),
+                    Err(e) => println!("Couldn't save state: {}", e),
+                }
+            } else if session.has_movie() {
+                println!("Couldn't load state: a movie is being recorded or replayed");
+            } else {
+                let loaded = fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|state| c8.load_state(&state).map_err(|e| e.to_string()));
+                match loaded {
+                    Ok(()) => println!("Loaded state from {}", path),
+                    Err(e) => println!("Couldn't load state: {}", e),
+                }
+            }
+        }
+
+        // While rewinding the program does not run, a frame is stepped back
+        // for every frame that is due instead.
+        let rewinding =
+            !session.has_movie() && rewind.is_enabled() && window.is_key_down(REWIND_KEY);
+        // While the debugger has control the program and its timers are
+        // stopped.
+        let mut halted = false;
+
+        for _ in 0..scheduler.frames_due() {
+            if rewinding {
+                if let Some(state) = rewind.pop() {
+                    // The history only holds states saved from this machine.
+                    c8.load_state(state).unwrap();
+                }
+                continue;
+            }
+
+            let completed = session.run_frame(&mut scheduler, &mut c8, keypad(&window));
+            if let Some(notice) = session.notice() {
+                println!("{}", notice);
+            }
+            match completed {
+                Ok(true) => (),
+                Ok(false) => {
+                    halted = true;
+                    break;
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    break 'frames;
+                }
+            }
+
+            // Saving a state every frame is skipped when nothing keeps it.
+            if rewind.is_enabled() {
+                rewind.push(c8.save_state());
+            }
+            if c8.has_exited() {
+                break;
+            }
+        }
+
+        // The sound produced by the Chip-8 interpreter has only one tone.
+        // The frequency of this tone is decided by the author of the
+        // interpreter. XO-CHIP programs can replace it with their own audio
+        // pattern.
+        buzzer.update(&c8);
+        if c8.st > 0 && !rewinding && !halted && !settings.silent {
+            audio_sink.play();
+        } else {
+            audio_sink.pause();
+        }
+
+        // Update Window
+        // The buffer has the size of the current resolution. The window keeps
+        // its size when SUPER-CHIP programs switch resolution, minifb scales
+        // the buffer to fit it.
+        window
+            .update_with_buffer(&c8.framebuffer(), c8.width(), c8.height())
+            .unwrap();
+    }
+
+    info!(target: "timing", "{}", scheduler.stats());
+    session.finish();
+}
+
+// The hexadecimal keypad is mapped to the left side of the keyboard:
+//  1 2 3 C        1 2 3 4
+//  4 5 6 D  --->  Q W E R
+//  7 8 9 E        A S D F
+//  A 0 B F        Z X C V
+fn keypad(window: &Window) -> [bool; 16] {
+    let mut key_press: [bool; 16] = [false; 16];
+
+    if let Some(keys) = window.get_keys() {
+        for t in keys {
+            match t {
+                Key::Key1 => key_press[0x1] = true,
+                Key::Key2 => key_press[0x2] = true,
+                Key::Key3 => key_press[0x3] = true,
+                Key::Key4 => key_press[0xc] = true,
+                Key::Q => key_press[0x4] = true,
+                Key::W => key_press[0x5] = true,
+                Key::E => key_press[0x6] = true,
+                Key::R => key_press[0xd] = true,
+                Key::A => key_press[0x7] = true,
+                Key::S => key_press[0x8] = true,
+                Key::D => key_press[0x9] = true,
+                Key::F => key_press[0xe] = true,
+                Key::Z => key_press[0xa] = true,
+                Key::X => key_press[0x0] = true,
+                Key::C => key_press[0xb] = true,
+                Key::V => key_press[0xf] = true,
+                _ => (),
+            }
+        }
+    }
+
+    key_press
+}
+
+// Path of the file holding save state `slot` of the ROM at `file_name`.
+fn slot_path(file_name: &str, slot: usize) -> String {
+    format!("{}.{}.state", file_name, slot)
+}
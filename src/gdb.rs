@@ -0,0 +1,579 @@
+// A stub for the GDB remote serial protocol, so debuggers that speak it can
+// control a ROM running in a frontend. It listens on a local TCP port and the
+// frontends ask it before every instruction whether the program may run.
+// Source: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//
+// Registers are numbered V0-VF (0-15), I (16), PC (17), SP (18), DT (19) and
+// ST (20). I and PC are 16 bits, little endian, the others a byte. SP is the
+// depth of the stack and can't be changed.
+
+use chip8::C8;
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+const REGISTERS: usize = 21;
+
+// Signals reported when the program stops.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub {
+    // None once the debugger detached or the connection broke.
+    stream: Option<TcpStream>,
+    // Bytes received that don't form a whole packet yet.
+    received: Vec<u8>,
+    breakpoints: BTreeSet<u16>,
+    halted: bool,
+    stepping: bool,
+    no_ack: bool,
+}
+
+impl GdbStub {
+    /// Waits for a debugger to connect on `port` of the loopback interface.
+    /// The program starts halted, so breakpoints can be set before it runs.
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        // Messages go to stderr, stdout may hold the output of `--headless`.
+        eprintln!("Waiting for gdb on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        eprintln!("gdb connected from {}", peer);
+        GdbStub::new(stream)
+    }
+
+    // Serves the debugger connected through `stream`.
+    fn new(stream: TcpStream) -> io::Result<GdbStub> {
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream: Some(stream),
+            received: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            halted: true,
+            stepping: false,
+            no_ack: false,
+        })
+    }
+
+    /// Handles the debugger's requests and returns whether the next
+    /// instruction may be executed. While it returns false the debugger has
+    /// control and the frontend should neither run the program nor tick the
+    /// timers.
+    pub fn poll(&mut self, c8: &mut C8) -> bool {
+        if self.stream.is_none() {
+            return true;
+        }
+        if let Err(e) = self.serve(c8) {
+            eprintln!("gdb disconnected: {}", e);
+            self.stream = None;
+            return true;
+        }
+        !self.halted
+    }
+
+    fn serve(&mut self, c8: &mut C8) -> io::Result<()> {
+        // The instruction a continue or step resumes at always runs, so
+        // continuing from a breakpoint doesn't stop on it again.
+        if !self.halted && (self.stepping || self.breakpoints.contains(&c8.pc)) {
+            self.stop(SIGTRAP)?;
+        }
+
+        self.receive()?;
+        while let Some(packet) = self.next_packet()? {
+            // Nothing is sent on resuming, the stop is reported later.
+            if let Some(response) = self.handle(&packet, c8) {
+                self.send(&response)?;
+            }
+            if self.stream.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self, signal: u8) -> io::Result<()> {
+        self.halted = true;
+        self.stepping = false;
+        self.send(&format!("S{:02x}", signal))
+    }
+
+    // Reads whatever arrived without waiting for more.
+    fn receive(&mut self) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+        stream.set_nonblocking(true)?;
+        let mut buf = [0; 4096];
+        let result = loop {
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    break Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "connection closed",
+                    ))
+                }
+                Ok(n) => self.received.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        result
+    }
+
+    // Takes the next `$data#checksum` packet out of the received bytes,
+    // acknowledging it. A lone 0x03 byte interrupts the running program.
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.received.first() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    self.received.remove(0);
+                    if !self.halted {
+                        self.stop(SIGINT)?;
+                    }
+                }
+                Some(b'$') => break,
+                // Acknowledgements, and noise between packets.
+                Some(_) => {
+                    self.received.remove(0);
+                }
+            }
+        }
+
+        let end = match self.received.iter().position(|b| *b == b'#') {
+            Some(end) if self.received.len() >= end + 3 => end,
+            _ => return Ok(None),
+        };
+        let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        let valid = checksum == Some(sum(data));
+        if !self.no_ack {
+            self.write(if valid { b"+" } else { b"-" })?;
+        }
+        if !valid {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, sum(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+
+    // The response to a packet, None when the program was resumed. Unknown
+    // packets get an empty response, which tells gdb they aren't supported.
+    fn handle(&mut self, packet: &str, c8: &mut C8) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let response = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => encode(&registers(c8)),
+            "G" => match decode(args) {
+                Some(bytes) if bytes.len() == register_bytes() => {
+                    let mut rest = &bytes[..];
+                    for n in 0..REGISTERS {
+                        let (value, tail) = rest.split_at(register_size(n));
+                        set_register(c8, n, value);
+                        rest = tail;
+                    }
+                    ok()
+                }
+                _ => error(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS => {
+                    let offset: usize = (0..n).map(register_size).sum();
+                    encode(&registers(c8)[offset..offset + register_size(n)])
+                }
+                _ => error(),
+            },
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let value = decode(value)?;
+                    Some((n, value))
+                });
+                match register {
+                    Some((n, value)) if n < REGISTERS && value.len() == register_size(n) => {
+                        set_register(c8, n, &value);
+                        ok()
+                    }
+                    _ => error(),
+                }
+            }
+            "m" => match range(args, c8) {
+                Some((addr, len)) => encode(&c8.ram()[addr..addr + len]),
+                None => error(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range_args, data)| {
+                    let (addr, len) = range(range_args, c8)?;
+                    let data = decode(data)?;
+                    if data.len() == len {
+                        Some((addr, data))
+                    } else {
+                        None
+                    }
+                });
+                match write {
+                    Some((addr, data)) => {
+                        c8.ram_mut()[addr..addr + data.len()].copy_from_slice(&data);
+                        ok()
+                    }
+                    None => error(),
+                }
+            }
+            "Z" | "z" => match breakpoint(args) {
+                Some(addr) => {
+                    if command == "Z" {
+                        self.breakpoints.insert(addr);
+                    } else {
+                        self.breakpoints.remove(&addr);
+                    }
+                    ok()
+                }
+                None => String::new(),
+            },
+            "c" | "s" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    c8.pc = addr;
+                }
+                self.halted = false;
+                self.stepping = command == "s";
+                return None;
+            }
+            "D" | "k" => {
+                if command == "D" {
+                    let _ = self.send(&ok());
+                }
+                eprintln!("gdb detached");
+                self.detach();
+                return None;
+            }
+            "H" => ok(),
+            "T" => ok(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Some(response)
+    }
+
+    // The program keeps running without the debugger.
+    fn detach(&mut self) {
+        self.halted = false;
+        self.stepping = false;
+        self.breakpoints.clear();
+        self.stream = None;
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match args.split_once(',').and_then(|(offset, len)| {
+                Some((
+                    usize::from_str_radix(offset, 16).ok()?,
+                    usize::from_str_radix(len, 16).ok()?,
+                ))
+            }) {
+                Some((offset, len)) => {
+                    let xml = target_xml();
+                    let start = offset.min(xml.len());
+                    let end = (start + len).min(xml.len());
+                    let more = if end < xml.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, &xml[start..end])
+                }
+                None => error(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                ok()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl Drop for GdbStub {
+    // Tells the debugger the program is gone when the frontend stops.
+    fn drop(&mut self) {
+        let _ = self.send("W00");
+    }
+}
+
+fn ok() -> String {
+    "OK".to_string()
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|j| u8::from_str_radix(hex.get(j..j + 2)?, 16).ok())
+        .collect()
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+fn register_bytes() -> usize {
+    (0..REGISTERS).map(register_size).sum()
+}
+
+fn registers(c8: &C8) -> Vec<u8> {
+    let mut bytes = c8.v.to_vec();
+    bytes.extend_from_slice(&c8.i().to_le_bytes());
+    bytes.extend_from_slice(&c8.pc.to_le_bytes());
+    bytes.push(c8.stack().len() as u8);
+    bytes.push(c8.dt);
+    bytes.push(c8.st);
+    bytes
+}
+
+fn set_register(c8: &mut C8, n: usize, value: &[u8]) {
+    match n {
+        0..=15 => c8.v[n] = value[0],
+        16 => c8.set_i(u16::from_le_bytes([value[0], value[1]])),
+        17 => c8.pc = u16::from_le_bytes([value[0], value[1]]),
+        19 => c8.dt = value[0],
+        20 => c8.st = value[0],
+        // The stack pointer is read only.
+        _ => (),
+    }
+}
+
+// `addr,length` of memory inside RAM.
+fn range(args: &str, c8: &C8) -> Option<(usize, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    // The remote picks both, so their sum can overflow.
+    if addr.checked_add(len)? > c8.ram().len() {
+        return None;
+    }
+    Some((addr, len))
+}
+
+// `type,addr,kind` of a breakpoint, software and hardware breakpoints are
+// the same to the stub. Watchpoints aren't supported.
+fn breakpoint(args: &str) -> Option<u16> {
+    let mut fields = args.split(',');
+    match fields.next()? {
+        "0" | "1" => u16::from_str_radix(fields.next()?, 16).ok(),
+        _ => None,
+    }
+}
+
+// Describes the registers to gdb. There is no `<architecture>`: gdb knows no
+// CHIP-8 architecture and only warns about names it doesn't know, so the registers
+// are all it gets. It can still read and write them and memory, and step and
+// set breakpoints, but not disassemble.
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">",
+    );
+    for x in 0..16 {
+        xml += &format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", x);
+    }
+    xml += "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+            <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+            <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\
+            <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\
+            <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\
+            </feature></target>";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Quirks;
+    use std::time::{Duration, Instant};
+
+    // The debugger's end of a loopback connection to a stub.
+    struct Client {
+        stream: TcpStream,
+        received: Vec<u8>,
+    }
+
+    impl Client {
+        fn connect() -> (Client, GdbStub) {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            stream.set_nonblocking(true).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            let client = Client {
+                stream,
+                received: Vec::new(),
+            };
+            (client, GdbStub::new(server).unwrap())
+        }
+
+        fn send(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, sum(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+        }
+
+        // Polls the stub until it has sent `len` bytes.
+        fn read(&mut self, stub: &mut GdbStub, c8: &mut C8, len: usize) -> Vec<u8> {
+            let start = Instant::now();
+            loop {
+                let mut buf = [0; 4096];
+                match self.stream.read(&mut buf) {
+                    Ok(n) => self.received.extend_from_slice(&buf[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                    Err(e) => panic!("{}", e),
+                }
+                if self.received.len() >= len {
+                    break;
+                }
+                assert!(start.elapsed() < Duration::from_secs(5), "no reply");
+                stub.poll(c8);
+            }
+            self.received.drain(..len).collect()
+        }
+
+        // Reads the next packet, checking its checksum.
+        fn reply(&mut self, stub: &mut GdbStub, c8: &mut C8) -> String {
+            assert_eq!(self.read(stub, c8, 1), b"$");
+            let mut data = Vec::new();
+            loop {
+                match self.read(stub, c8, 1)[0] {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = String::from_utf8(self.read(stub, c8, 2)).unwrap();
+            assert_eq!(checksum, format!("{:02x}", sum(&data)));
+            String::from_utf8(data).unwrap()
+        }
+
+        // Sends a packet and returns the reply after its acknowledgement.
+        fn request(&mut self, stub: &mut GdbStub, c8: &mut C8, data: &str) -> String {
+            self.send(data);
+            assert_eq!(self.read(stub, c8, 1), b"+");
+            self.reply(stub, c8)
+        }
+
+        // Sends a packet that resumes the program and waits until the stub
+        // lets it run.
+        fn resume(&mut self, stub: &mut GdbStub, c8: &mut C8, data: &str) {
+            self.send(data);
+            let start = Instant::now();
+            while !stub.poll(c8) {
+                assert!(start.elapsed() < Duration::from_secs(5), "not resumed");
+            }
+            assert_eq!(self.read(stub, c8, 1), b"+");
+        }
+    }
+
+    #[test]
+    fn serves_a_debugger() {
+        let mut c8 = C8::new(Quirks::default());
+        // LD V1, 0x2A; LD V2, 0x03; LD V3, 0x04; JP 0x206
+        c8.load_ram(&[0x61, 0x2A, 0x62, 0x03, 0x63, 0x04, 0x12, 0x06])
+            .unwrap();
+        let (mut client, mut stub) = Client::connect();
+        let c8 = &mut c8;
+
+        // The program starts halted.
+        assert!(!stub.poll(c8));
+        assert_eq!(client.request(&mut stub, c8, "?"), "S05");
+
+        // A corrupted packet is refused and gets no reply.
+        client.stream.write_all(b"$g#00").unwrap();
+        assert_eq!(client.read(&mut stub, c8, 1), b"-");
+
+        // V0-VF, then I and PC little endian, SP, DT and ST.
+        let registers = client.request(&mut stub, c8, "g");
+        assert_eq!(registers.len(), 2 * register_bytes());
+        assert_eq!(&registers[32..42], "0000000200");
+
+        assert_eq!(client.request(&mut stub, c8, "m200,4"), "612a6203");
+        assert_eq!(client.request(&mut stub, c8, "M300,2:beef"), "OK");
+        assert_eq!(c8.ram()[0x300..0x302], [0xBE, 0xEF]);
+        assert_eq!(client.request(&mut stub, c8, "m1000,1"), "E01");
+
+        // A step runs one instruction and stops again.
+        client.resume(&mut stub, c8, "s");
+        c8.run(&[false; 16]).unwrap();
+        assert_eq!(client.reply(&mut stub, c8), "S05");
+        assert_eq!(c8.v[1], 0x2A);
+        assert!(!stub.poll(c8));
+
+        // Continuing runs up to a breakpoint.
+        assert_eq!(client.request(&mut stub, c8, "Z0,206,2"), "OK");
+        client.resume(&mut stub, c8, "c");
+        while stub.poll(c8) {
+            c8.run(&[false; 16]).unwrap();
+        }
+        assert_eq!(c8.pc, 0x206);
+        assert_eq!(client.reply(&mut stub, c8), "S05");
+        assert_eq!((c8.v[2], c8.v[3]), (0x03, 0x04));
+
+        // Without the breakpoint the program keeps running.
+        assert_eq!(client.request(&mut stub, c8, "z0,206,2"), "OK");
+        client.resume(&mut stub, c8, "c");
+        for _ in 0..10 {
+            c8.run(&[false; 16]).unwrap();
+            assert!(stub.poll(c8));
+        }
+
+        // Interrupting it stops it again, and without acknowledgements
+        // replies follow the packets directly.
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(&mut stub, c8), "S02");
+        client.send("QStartNoAckMode");
+        assert_eq!(client.read(&mut stub, c8, 1), b"+");
+        assert_eq!(client.reply(&mut stub, c8), "OK");
+        client.send("m206,2");
+        assert_eq!(client.reply(&mut stub, c8), "1206");
+    }
+
+    #[test]
+    fn ranges_stay_inside_ram() {
+        let c8 = C8::new(Quirks::default());
+        assert_eq!(range("200,10", &c8), Some((0x200, 0x10)));
+        assert_eq!(range("ff0,10", &c8), Some((0xFF0, 0x10)));
+        assert_eq!(range("ff0,11", &c8), None);
+        assert_eq!(range("1,ffffffffffffffff", &c8), None);
+    }
+}
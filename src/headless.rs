@@ -0,0 +1,159 @@
+// Runs a ROM without a window or an audio device and reports the final state
+// of the machine, for automated tests on machines without a display.
+
+use crate::gdb::GdbStub;
+use chip8::movie::{self, Movie, MovieError};
+use chip8::{C8Error, C8};
+use std::fmt::Write;
+use std::thread;
+use std::time::Duration;
+
+/// How long a headless run lasts.
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    /// Number of instructions to execute.
+    Cycles(u64),
+    /// Number of 60 Hz frames to run.
+    Frames(u64),
+}
+
+/// What a headless run did before it stopped.
+pub struct Summary {
+    pub cycles: u64,
+    pub frames: u64,
+    /// The fault that stopped the program early, if any.
+    pub error: Option<C8Error>,
+    /// How a replayed movie went differently from the recording, if it did.
+    pub desync: Option<MovieError>,
+}
+
+/// Executes the program loaded into `c8` until `limit` is reached, the
+/// program exits or it faults. Frames are run with `C8::run_frame` at the
+/// speed set on `c8`, like the window does 60 times a second.
+///
+/// Keys are taken from `replay` frame by frame, without it no key is ever
+/// pressed.
+///
+/// While `gdb` has control the program doesn't run.
+pub fn run(
+    c8: &mut C8,
+    limit: Limit,
+    replay: Option<&Movie>,
+    mut gdb: Option<&mut GdbStub>,
+) -> Summary {
+    let (max_cycles, max_frames) = match limit {
+        Limit::Cycles(cycles) => (cycles, u64::MAX),
+        Limit::Frames(frames) => (u64::MAX, frames),
+    };
+
+    let mut summary = Summary {
+        cycles: 0,
+        frames: 0,
+        error: None,
+        desync: None,
+    };
+    while summary.frames < max_frames && !c8.has_exited() {
+        let frame = summary.frames as usize;
+        let key_press = replay
+            .and_then(|movie| movie.keys(frame))
+            .unwrap_or([false; 16]);
+
+        let mut halted = false;
+        let cycles = &mut summary.cycles;
+        let completed = c8.run_frame_with(&key_press, |c8| {
+            if *cycles >= max_cycles {
+                return false;
+            }
+            if let Some(gdb) = &mut gdb {
+                if !gdb.poll(c8) {
+                    halted = true;
+                    return false;
+                }
+            }
+            *cycles += 1;
+            true
+        });
+        match completed {
+            Ok(true) => (),
+            Ok(false) if halted => {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            Ok(false) => break,
+            Err(e) => {
+                summary.error = Some(e);
+                break;
+            }
+        }
+
+        // 60 Hz
+        summary.frames += 1;
+        if let Some(movie) = replay {
+            if let Err(e) = movie.verify(summary.frames as usize, c8) {
+                summary.desync = Some(e);
+                break;
+            }
+        }
+    }
+
+    summary
+}
+
+/// Formats the registers, hashes of memory and the display, and the display
+/// itself as text: `.` for pixels that are off, `#` for plain pixels and the
+/// hexadecimal bitplane mask for XO-CHIP colours.
+pub fn dump(c8: &C8, summary: &Summary) -> String {
+    let mut out = String::new();
+
+    // Writing to a String cannot fail.
+    let _ = writeln!(out, "Cycles: {}", summary.cycles);
+    let _ = writeln!(out, "Frames: {}", summary.frames);
+    match &summary.error {
+        Some(e) => {
+            let _ = writeln!(out, "Error: {}", e);
+        }
+        None if c8.has_exited() => {
+            let _ = writeln!(out, "Exited");
+        }
+        None => (),
+    }
+    if let Some(e) = &summary.desync {
+        let _ = writeln!(out, "{}", e);
+    }
+
+    let _ = writeln!(
+        out,
+        "PC: {:#06x}  I: {:#06x}  DT: {:#04x}  ST: {:#04x}",
+        c8.pc,
+        c8.i(),
+        c8.dt,
+        c8.st
+    );
+    for (j, row) in c8.v.chunks(8).enumerate() {
+        let regs: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(k, v)| format!("V{:X}: {:#04x}", j * 8 + k, v))
+            .collect();
+        let _ = writeln!(out, "{}", regs.join("  "));
+    }
+    let stack: Vec<String> = c8.stack().iter().map(|a| format!("{:#05x}", a)).collect();
+    let _ = writeln!(out, "Stack: [{}]", stack.join(", "));
+
+    let _ = writeln!(out, "RAM hash: {:016x}", movie::hash(c8.ram()));
+    let _ = writeln!(out, "Display hash: {:016x}", movie::hash(c8.pixels()));
+    let _ = writeln!(out, "Display: {}x{}", c8.width(), c8.height());
+    for row in c8.pixels().chunks(c8.width()) {
+        let line: String = row
+            .iter()
+            .map(|px| match px {
+                0 => '.',
+                1 => '#',
+                mask => std::char::from_digit(*mask as u32, 16).unwrap_or('?'),
+            })
+            .collect();
+        let _ = writeln!(out, "{}", line);
+    }
+
+    out
+}
@@ -0,0 +1,484 @@
+use crate::quirks::Quirks;
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// Register operands are indices into V0-VF. Every opcode decodes to exactly
+/// one instruction, opcodes that mean nothing decode to `Unknown`, and
+/// `encode` turns any instruction back into the opcode it came from.
+/// Whether an extension instruction may run is up to the interpreter, see
+/// `Instruction::is_xo_chip`.
+///
+/// The SUPER-CHIP instructions always run. They only take opcodes CHIP-8
+/// left meaningless: 00CN and 00FB-00FF would be SYS calls into the VIP
+/// interpreter itself at 0x000-0x1FF, FX30/FX75/FX85 are unused and DXY0
+/// draws nothing on the VIP, so no CHIP-8 program changes behaviour.
+/// XO-CHIP on the other hand changes the memory size and how far skips go,
+/// which is why it needs the `xo_chip` quirk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0nnn - SYS addr
+    Sys(u16),
+    /// 00E0 - CLS
+    Cls,
+    /// 00EE - RET
+    Ret,
+    /// 00Cn - SCD nibble
+    ScrollDown(u8),
+    /// 00Dn - SCU nibble
+    ScrollUp(u8),
+    /// 00FB - SCR
+    ScrollRight,
+    /// 00FC - SCL
+    ScrollLeft,
+    /// 00FD - EXIT
+    Exit,
+    /// 00FE - LOW
+    Low,
+    /// 00FF - HIGH
+    High,
+    /// 1nnn - JP addr
+    Jump(u16),
+    /// 2nnn - CALL addr
+    Call(u16),
+    /// 3xkk - SE Vx, byte
+    SkipEqByte(usize, u8),
+    /// 4xkk - SNE Vx, byte
+    SkipNeByte(usize, u8),
+    /// 5xy0 - SE Vx, Vy
+    SkipEqReg(usize, usize),
+    /// 5xy2 - LD [I], Vx-Vy
+    StoreRange(usize, usize),
+    /// 5xy3 - LD Vx-Vy, [I]
+    LoadRange(usize, usize),
+    /// 6xkk - LD Vx, byte
+    LoadByte(usize, u8),
+    /// 7xkk - ADD Vx, byte
+    AddByte(usize, u8),
+    /// 8xy0 - LD Vx, Vy
+    LoadReg(usize, usize),
+    /// 8xy1 - OR Vx, Vy
+    Or(usize, usize),
+    /// 8xy2 - AND Vx, Vy
+    And(usize, usize),
+    /// 8xy3 - XOR Vx, Vy
+    Xor(usize, usize),
+    /// 8xy4 - ADD Vx, Vy
+    AddReg(usize, usize),
+    /// 8xy5 - SUB Vx, Vy
+    Sub(usize, usize),
+    /// 8xy6 - SHR Vx, Vy
+    ShiftRight(usize, usize),
+    /// 8xy7 - SUBN Vx, Vy
+    SubN(usize, usize),
+    /// 8xyE - SHL Vx, Vy
+    ShiftLeft(usize, usize),
+    /// 9xy0 - SNE Vx, Vy
+    SkipNeReg(usize, usize),
+    /// Annn - LD I, addr
+    LoadI(u16),
+    /// Bnnn - JP V0, addr
+    JumpOffset(u16),
+    /// Cxkk - RND Vx, byte
+    Random(usize, u8),
+    /// Dxyn - DRW Vx, Vy, nibble
+    Draw(usize, usize, u8),
+    /// Ex9E - SKP Vx
+    SkipKey(usize),
+    /// ExA1 - SKNP Vx
+    SkipNotKey(usize),
+    /// F000 nnnn - LD I, LONG addr. The address is the next 16-bit word.
+    LoadILong,
+    /// Fn01 - PLANE n
+    Plane(u8),
+    /// F002 - AUDIO
+    Audio,
+    /// Fx07 - LD Vx, DT
+    LoadDelay(usize),
+    /// Fx0A - LD Vx, K
+    WaitKey(usize),
+    /// Fx15 - LD DT, Vx
+    SetDelay(usize),
+    /// Fx18 - LD ST, Vx
+    SetSound(usize),
+    /// Fx1E - ADD I, Vx
+    AddI(usize),
+    /// Fx29 - LD F, Vx
+    LoadFont(usize),
+    /// Fx30 - LD HF, Vx
+    LoadBigFont(usize),
+    /// Fx33 - LD B, Vx
+    Bcd(usize),
+    /// Fx3A - PITCH Vx
+    Pitch(usize),
+    /// Fx55 - LD [I], Vx
+    StoreRegs(usize),
+    /// Fx65 - LD Vx, [I]
+    LoadRegs(usize),
+    /// Fx75 - LD R, Vx
+    StoreFlags(usize),
+    /// Fx85 - LD Vx, R
+    LoadFlags(usize),
+    /// Any opcode that is not an instruction.
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decodes a 16-bit opcode.
+    pub fn decode(inst: u16) -> Instruction {
+        use Instruction::*;
+
+        // In these listings, the following variables are used:
+        // nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
+        // n or nibble - A 4-bit value, the lowest 4 bits of the instruction
+        // x - A 4-bit value, the lower 4 bits of the high byte of the instruction
+        // y - A 4-bit value, the upper 4 bits of the low byte of the instruction
+        // kk or byte - An 8-bit value, the lowest 8 bits of the instruction
+        // Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+        let nnn = inst & 0x0FFF;
+        let n = (inst & 0x000F) as u8;
+        let x = ((inst & 0x0F00) >> 8) as usize;
+        let y = ((inst & 0x00F0) >> 4) as usize;
+        let kk = (inst & 0x00FF) as u8;
+
+        match (inst >> 12, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => Cls,
+            (0x0, 0x0, 0xE, 0xE) => Ret,
+            (0x0, 0x0, 0xC, _) => ScrollDown(n),
+            (0x0, 0x0, 0xD, _) => ScrollUp(n),
+            (0x0, 0x0, 0xF, 0xB) => ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Exit,
+            (0x0, 0x0, 0xF, 0xE) => Low,
+            (0x0, 0x0, 0xF, 0xF) => High,
+            (0x0, _, _, _) => Sys(nnn),
+            (0x1, _, _, _) => Jump(nnn),
+            (0x2, _, _, _) => Call(nnn),
+            (0x3, _, _, _) => SkipEqByte(x, kk),
+            (0x4, _, _, _) => SkipNeByte(x, kk),
+            (0x5, _, _, 0x0) => SkipEqReg(x, y),
+            (0x5, _, _, 0x2) => StoreRange(x, y),
+            (0x5, _, _, 0x3) => LoadRange(x, y),
+            (0x6, _, _, _) => LoadByte(x, kk),
+            (0x7, _, _, _) => AddByte(x, kk),
+            (0x8, _, _, 0x0) => LoadReg(x, y),
+            (0x8, _, _, 0x1) => Or(x, y),
+            (0x8, _, _, 0x2) => And(x, y),
+            (0x8, _, _, 0x3) => Xor(x, y),
+            (0x8, _, _, 0x4) => AddReg(x, y),
+            (0x8, _, _, 0x5) => Sub(x, y),
+            (0x8, _, _, 0x6) => ShiftRight(x, y),
+            (0x8, _, _, 0x7) => SubN(x, y),
+            (0x8, _, _, 0xE) => ShiftLeft(x, y),
+            (0x9, _, _, 0x0) => SkipNeReg(x, y),
+            (0xA, _, _, _) => LoadI(nnn),
+            (0xB, _, _, _) => JumpOffset(nnn),
+            (0xC, _, _, _) => Random(x, kk),
+            (0xD, _, _, _) => Draw(x, y, n),
+            (0xE, _, 0x9, 0xE) => SkipKey(x),
+            (0xE, _, 0xA, 0x1) => SkipNotKey(x),
+            (0xF, 0x0, 0x0, 0x0) => LoadILong,
+            (0xF, _, 0x0, 0x1) => Plane(x as u8),
+            (0xF, 0x0, 0x0, 0x2) => Audio,
+            (0xF, _, 0x0, 0x7) => LoadDelay(x),
+            (0xF, _, 0x0, 0xA) => WaitKey(x),
+            (0xF, _, 0x1, 0x5) => SetDelay(x),
+            (0xF, _, 0x1, 0x8) => SetSound(x),
+            (0xF, _, 0x1, 0xE) => AddI(x),
+            (0xF, _, 0x2, 0x9) => LoadFont(x),
+            (0xF, _, 0x3, 0x0) => LoadBigFont(x),
+            (0xF, _, 0x3, 0x3) => Bcd(x),
+            (0xF, _, 0x3, 0xA) => Pitch(x),
+            (0xF, _, 0x5, 0x5) => StoreRegs(x),
+            (0xF, _, 0x6, 0x5) => LoadRegs(x),
+            (0xF, _, 0x7, 0x5) => StoreFlags(x),
+            (0xF, _, 0x8, 0x5) => LoadFlags(x),
+            _ => Unknown(inst),
+        }
+    }
+
+    /// Encodes the instruction back into its 16-bit opcode.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |x: usize, y: usize| ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4);
+        let x = |x: usize| xy(x, 0);
+
+        match *self {
+            Sys(nnn) => nnn & 0x0FFF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            SkipEqByte(vx, kk) => 0x3000 | x(vx) | kk as u16,
+            SkipNeByte(vx, kk) => 0x4000 | x(vx) | kk as u16,
+            SkipEqReg(vx, vy) => 0x5000 | xy(vx, vy),
+            StoreRange(vx, vy) => 0x5002 | xy(vx, vy),
+            LoadRange(vx, vy) => 0x5003 | xy(vx, vy),
+            LoadByte(vx, kk) => 0x6000 | x(vx) | kk as u16,
+            AddByte(vx, kk) => 0x7000 | x(vx) | kk as u16,
+            LoadReg(vx, vy) => 0x8000 | xy(vx, vy),
+            Or(vx, vy) => 0x8001 | xy(vx, vy),
+            And(vx, vy) => 0x8002 | xy(vx, vy),
+            Xor(vx, vy) => 0x8003 | xy(vx, vy),
+            AddReg(vx, vy) => 0x8004 | xy(vx, vy),
+            Sub(vx, vy) => 0x8005 | xy(vx, vy),
+            ShiftRight(vx, vy) => 0x8006 | xy(vx, vy),
+            SubN(vx, vy) => 0x8007 | xy(vx, vy),
+            ShiftLeft(vx, vy) => 0x800E | xy(vx, vy),
+            SkipNeReg(vx, vy) => 0x9000 | xy(vx, vy),
+            LoadI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            JumpOffset(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Random(vx, kk) => 0xC000 | x(vx) | kk as u16,
+            Draw(vx, vy, n) => 0xD000 | xy(vx, vy) | (n as u16 & 0xF),
+            SkipKey(vx) => 0xE09E | x(vx),
+            SkipNotKey(vx) => 0xE0A1 | x(vx),
+            LoadILong => 0xF000,
+            Plane(n) => 0xF001 | x(n as usize),
+            Audio => 0xF002,
+            LoadDelay(vx) => 0xF007 | x(vx),
+            WaitKey(vx) => 0xF00A | x(vx),
+            SetDelay(vx) => 0xF015 | x(vx),
+            SetSound(vx) => 0xF018 | x(vx),
+            AddI(vx) => 0xF01E | x(vx),
+            LoadFont(vx) => 0xF029 | x(vx),
+            LoadBigFont(vx) => 0xF030 | x(vx),
+            Bcd(vx) => 0xF033 | x(vx),
+            Pitch(vx) => 0xF03A | x(vx),
+            StoreRegs(vx) => 0xF055 | x(vx),
+            LoadRegs(vx) => 0xF065 | x(vx),
+            StoreFlags(vx) => 0xF075 | x(vx),
+            LoadFlags(vx) => 0xF085 | x(vx),
+            Unknown(inst) => inst,
+        }
+    }
+
+    /// Whether this is one of the XO-CHIP additions, which only run with the
+    /// `xo_chip` quirk.
+    pub fn is_xo_chip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            ScrollUp(_) | StoreRange(..) | LoadRange(..) | LoadILong | Plane(_) | Audio | Pitch(_)
+        )
+    }
+
+    /// Length in bytes of the instruction as `quirks` run it: 4 for the
+    /// XO-CHIP `LD I, LONG`, whose address is the next word, 2 for the rest.
+    pub fn len(&self, quirks: &Quirks) -> u16 {
+        match self {
+            Instruction::LoadILong if quirks.xo_chip => 4,
+            _ => 2,
+        }
+    }
+
+    /// Formats the instruction in Cowgod's mnemonics as `quirks` interpret
+    /// it, letting `label` name the addresses it refers to. Addresses without
+    /// a name are printed in hexadecimal.
+    pub fn format_with(&self, quirks: &Quirks, label: &dyn Fn(u16) -> Option<String>) -> String {
+        use Instruction::*;
+
+        let addr = |nnn: u16| label(nnn).unwrap_or_else(|| format!("0x{:03X}", nnn));
+
+        match *self {
+            Sys(nnn) => format!("SYS {}", addr(nnn)),
+            Cls => "CLS".to_string(),
+            Ret => "RET".to_string(),
+            ScrollDown(n) => format!("SCD {}", n),
+            ScrollUp(n) => format!("SCU {}", n),
+            ScrollRight => "SCR".to_string(),
+            ScrollLeft => "SCL".to_string(),
+            Exit => "EXIT".to_string(),
+            Low => "LOW".to_string(),
+            High => "HIGH".to_string(),
+            Jump(nnn) => format!("JP {}", addr(nnn)),
+            Call(nnn) => format!("CALL {}", addr(nnn)),
+            SkipEqByte(x, kk) => format!("SE V{:X}, 0x{:02X}", x, kk),
+            SkipNeByte(x, kk) => format!("SNE V{:X}, 0x{:02X}", x, kk),
+            SkipEqReg(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            StoreRange(x, y) => format!("LD [I], V{:X}-V{:X}", x, y),
+            LoadRange(x, y) => format!("LD V{:X}-V{:X}, [I]", x, y),
+            LoadByte(x, kk) => format!("LD V{:X}, 0x{:02X}", x, kk),
+            AddByte(x, kk) => format!("ADD V{:X}, 0x{:02X}", x, kk),
+            LoadReg(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            SubN(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            SkipNeReg(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            LoadI(nnn) => format!("LD I, {}", addr(nnn)),
+            // As BXNN the register is the highest nibble of the address.
+            JumpOffset(nnn) if quirks.jump_uses_vx => {
+                format!("JP V{:X}, {}", nnn >> 8, addr(nnn))
+            }
+            JumpOffset(nnn) => format!("JP V0, {}", addr(nnn)),
+            Random(x, kk) => format!("RND V{:X}, 0x{:02X}", x, kk),
+            Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey(x) => format!("SKP V{:X}", x),
+            SkipNotKey(x) => format!("SKNP V{:X}", x),
+            LoadILong => "LD I, LONG".to_string(),
+            Plane(n) => format!("PLANE {}", n),
+            Audio => "AUDIO".to_string(),
+            LoadDelay(x) => format!("LD V{:X}, DT", x),
+            WaitKey(x) => format!("LD V{:X}, K", x),
+            SetDelay(x) => format!("LD DT, V{:X}", x),
+            SetSound(x) => format!("LD ST, V{:X}", x),
+            AddI(x) => format!("ADD I, V{:X}", x),
+            LoadFont(x) => format!("LD F, V{:X}", x),
+            LoadBigFont(x) => format!("LD HF, V{:X}", x),
+            Bcd(x) => format!("LD B, V{:X}", x),
+            Pitch(x) => format!("PITCH V{:X}", x),
+            StoreRegs(x) => format!("LD [I], V{:X}", x),
+            LoadRegs(x) => format!("LD V{:X}, [I]", x),
+            StoreFlags(x) => format!("LD R, V{:X}", x),
+            LoadFlags(x) => format!("LD V{:X}, R", x),
+            Unknown(inst) => format!("dw 0x{:04X}", inst),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(&Quirks::default(), &|_| None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction::{self, *};
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn every_opcode_round_trips() {
+        for op in 0..=0xFFFF {
+            let instruction = Instruction::decode(op);
+            assert_eq!(instruction.encode(), op, "{:?}", instruction);
+            assert_eq!(Instruction::decode(instruction.encode()), instruction);
+        }
+    }
+
+    #[test]
+    fn xo_chip_instructions() {
+        for instruction in [
+            ScrollUp(1),
+            StoreRange(1, 2),
+            LoadRange(1, 2),
+            LoadILong,
+            Plane(3),
+            Audio,
+            Pitch(4),
+        ]
+        .iter()
+        {
+            assert!(instruction.is_xo_chip(), "{:?}", instruction);
+        }
+        for instruction in [
+            Sys(0x300),
+            Cls,
+            ScrollDown(1),
+            ScrollRight,
+            High,
+            Draw(1, 2, 0),
+            LoadBigFont(1),
+            StoreFlags(7),
+            Unknown(0x5001),
+        ]
+        .iter()
+        {
+            assert!(!instruction.is_xo_chip(), "{:?}", instruction);
+        }
+    }
+
+    #[test]
+    fn long_loads_are_four_bytes_with_xo_chip() {
+        assert_eq!(LoadILong.len(&Quirks::xo_chip()), 4);
+        assert_eq!(LoadILong.len(&Quirks::default()), 2);
+        assert_eq!(Jump(0x200).len(&Quirks::xo_chip()), 2);
+    }
+
+    #[test]
+    fn formats_mnemonics() {
+        let cases = [
+            (Sys(0x300), "SYS 0x300"),
+            (Cls, "CLS"),
+            (Ret, "RET"),
+            (ScrollDown(4), "SCD 4"),
+            (ScrollUp(4), "SCU 4"),
+            (ScrollRight, "SCR"),
+            (ScrollLeft, "SCL"),
+            (Exit, "EXIT"),
+            (Low, "LOW"),
+            (High, "HIGH"),
+            (Jump(0x208), "JP 0x208"),
+            (Call(0x208), "CALL 0x208"),
+            (SkipEqByte(1, 0x2A), "SE V1, 0x2A"),
+            (SkipNeByte(1, 0x2A), "SNE V1, 0x2A"),
+            (SkipEqReg(1, 2), "SE V1, V2"),
+            (StoreRange(1, 2), "LD [I], V1-V2"),
+            (LoadRange(1, 2), "LD V1-V2, [I]"),
+            (LoadByte(0xA, 0xFF), "LD VA, 0xFF"),
+            (AddByte(0xA, 1), "ADD VA, 0x01"),
+            (LoadReg(1, 2), "LD V1, V2"),
+            (Or(1, 2), "OR V1, V2"),
+            (And(1, 2), "AND V1, V2"),
+            (Xor(1, 2), "XOR V1, V2"),
+            (AddReg(1, 2), "ADD V1, V2"),
+            (Sub(1, 2), "SUB V1, V2"),
+            (ShiftRight(1, 2), "SHR V1, V2"),
+            (SubN(1, 2), "SUBN V1, V2"),
+            (ShiftLeft(1, 2), "SHL V1, V2"),
+            (SkipNeReg(1, 2), "SNE V1, V2"),
+            (LoadI(0x300), "LD I, 0x300"),
+            (JumpOffset(0x300), "JP V0, 0x300"),
+            (Random(3, 0x0F), "RND V3, 0x0F"),
+            (Draw(1, 2, 5), "DRW V1, V2, 5"),
+            (SkipKey(5), "SKP V5"),
+            (SkipNotKey(5), "SKNP V5"),
+            (LoadILong, "LD I, LONG"),
+            (Plane(3), "PLANE 3"),
+            (Audio, "AUDIO"),
+            (LoadDelay(1), "LD V1, DT"),
+            (WaitKey(1), "LD V1, K"),
+            (SetDelay(1), "LD DT, V1"),
+            (SetSound(1), "LD ST, V1"),
+            (AddI(1), "ADD I, V1"),
+            (LoadFont(1), "LD F, V1"),
+            (LoadBigFont(1), "LD HF, V1"),
+            (Bcd(1), "LD B, V1"),
+            (Pitch(1), "PITCH V1"),
+            (StoreRegs(0xF), "LD [I], VF"),
+            (LoadRegs(0xF), "LD VF, [I]"),
+            (StoreFlags(7), "LD R, V7"),
+            (LoadFlags(7), "LD V7, R"),
+            (Unknown(0x5001), "dw 0x5001"),
+        ];
+        for (instruction, text) in cases.iter() {
+            assert_eq!(instruction.to_string(), *text);
+        }
+
+        let quirks = Quirks::default();
+        let label = |addr| Some(format!("L{:03X}", addr));
+        assert_eq!(Jump(0x208).format_with(&quirks, &label), "JP L208");
+        assert_eq!(Call(0x20A).format_with(&quirks, &label), "CALL L20A");
+        assert_eq!(LoadI(0x300).format_with(&quirks, &label), "LD I, L300");
+
+        // As BXNN the register is part of the address.
+        let bxnn = Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        };
+        assert_eq!(JumpOffset(0x342).format_with(&bxnn, &label), "JP V3, L342");
+    }
+}
@@ -0,0 +1,41 @@
+//! CHIP-8 Interpreter/Emulator core.
+//!
+//! The interpreter itself has no dependency on any windowing or audio
+//! library, so it can be embedded in other tools and tests. A frontend loads a
+//! ROM with `C8::load_ram`, calls `C8::run_frame` 60 times a second, or
+//! `C8::run` once per instruction and `C8::tick_timers` at 60 Hz, and reads
+//! back `C8::framebuffer` to draw the screen. `Quirks` selects how ambiguous
+//! instructions behave. Faults caused by the program are returned as
+//! `C8Error` instead of panicking.
+//!
+//! `disasm` turns a ROM back into a listing of mnemonics, and `asm` assembles
+//! the same mnemonics into a ROM. `movie` records the keys pressed during a
+//! run so it can be replayed exactly, and `debug` runs a program with
+//! breakpoints and single steps. `timing` paces frames by the wall clock, and
+//! `cdp1802` emulates the processor of the COSMAC VIP for programs that call
+//! machine code.
+//!
+//! What the interpreter does is logged through the `log` facade under the
+//! targets `cpu`, `memory`, `display`, `input`, `audio` and `timing`. Nothing
+//! is logged unless the application installs a logger.
+
+pub mod asm;
+mod c8;
+pub mod cdp1802;
+pub mod debug;
+pub mod disasm;
+mod error;
+mod instruction;
+pub mod movie;
+mod quirks;
+mod rng;
+mod state;
+pub mod timing;
+
+pub use c8::{
+    KeyWait, C8, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, PALETTE, PATTERN_SIZE, RAM_SIZE, WIDTH,
+    XO_RAM_SIZE,
+};
+pub use error::C8Error;
+pub use instruction::Instruction;
+pub use quirks::{LoadStore, Quirks, PRESETS};
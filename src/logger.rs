@@ -0,0 +1,89 @@
+// Writes the interpreter's log to stderr. What is logged is given with `--log`
+// as comma separated `target=level` pairs, and a bare level for all other
+// targets, for example `--log cpu=trace,input=debug` or `--log info`.
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+// The targets the interpreter logs to.
+const TARGETS: [&str; 6] = ["cpu", "memory", "display", "input", "audio", "timing"];
+
+struct Logger {
+    // Level of targets without one of their own.
+    default: LevelFilter,
+    targets: Vec<(&'static str, LevelFilter)>,
+}
+
+impl Logger {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(name, _)| *name == target)
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger with the levels given in `spec`.
+pub fn init(spec: &str) -> Result<(), String> {
+    let mut logger = Logger {
+        default: LevelFilter::Off,
+        targets: Vec::new(),
+    };
+    for part in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        match part.split_once('=') {
+            Some((target, level)) => {
+                let target = TARGETS
+                    .iter()
+                    .find(|name| **name == target)
+                    .ok_or_else(|| {
+                        format!(
+                            "Unknown log target: {} (expected one of {})",
+                            target,
+                            TARGETS.join(", ")
+                        )
+                    })?;
+                logger.targets.push((target, parse_level(level)?));
+            }
+            None => logger.default = parse_level(part)?,
+        }
+    }
+
+    // Messages above the highest level are filtered out by the log macros
+    // before they are formatted.
+    let max = logger
+        .targets
+        .iter()
+        .map(|(_, level)| *level)
+        .chain(Some(logger.default))
+        .max()
+        .unwrap_or(LevelFilter::Off);
+    log::set_logger(Box::leak(Box::new(logger))).map_err(|e| e.to_string())?;
+    log::set_max_level(max);
+    Ok(())
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level.parse().map_err(|_| {
+        format!(
+            "Unknown log level: {} (expected off, error, warn, info, debug or trace)",
+            level
+        )
+    })
+}
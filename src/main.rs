@@ -1,135 +1,352 @@
-use c8::{HEIGHT, RAM_SIZE, WIDTH};
-use minifb::{Key, Scale, Window, WindowOptions};
-use std::{env, fs};
-use rodio::Sink;
+use chip8::movie::Movie;
+use chip8::timing::Speed;
+use chip8::{asm, disasm, Quirks, C8, PRESETS};
+use gdb::GdbStub;
+use headless::Limit;
+use std::{env, fs, path::Path, process};
 
-mod c8;
+mod debugger;
+#[cfg(feature = "frontend")]
+mod frontend;
+mod gdb;
+mod headless;
+mod logger;
+#[cfg(any(feature = "frontend", feature = "terminal"))]
+mod session;
+#[cfg(feature = "terminal")]
+mod terminal;
+
+/// Settings of the frontends given on the command line.
+pub struct Settings {
+    /// Frequency of the buzzer in Hz.
+    pub tone: f32,
+    /// Whether the sound timer makes no sound.
+    pub silent: bool,
+    /// How far back the rewind key can go, 0 disables rewinding.
+    pub rewind_seconds: f64,
+    /// Movie being recorded or replayed.
+    pub movie: Option<MovieMode>,
+    /// Remote debugger controlling the program.
+    pub gdb: Option<GdbStub>,
+}
+
+/// What the window does with a movie.
+pub enum MovieMode {
+    /// Record the keys pressed and write the movie to the path on exit.
+    Record(String, Movie),
+    /// Take the keys from the movie instead of the keyboard.
+    Replay(Movie),
+}
 
 fn main() {
-    println!("CHIP-8 Interpreter/Emulator");
-    let mut c8 = c8::C8::new();
+    // Options are given as `--name value` anywhere on the command line, the
+    // remaining arguments are the ROM path and the update rate.
+    let mut args: Vec<String> = Vec::new();
+    let mut quirks = Quirks::default();
+    let mut tone: f32 = 440.0;
+    let mut headless = false;
+    let mut debug = false;
+    let mut tui = false;
+    let mut in_terminal = false;
+    let mut silent = false;
+    let mut vip_timing = false;
+    let mut vip_memory = false;
+    let mut machine_code = false;
+    let mut limit = None;
+    let mut state_file = None;
+    let mut rewind_seconds: f64 = 10.0;
+    let mut seed = None;
+    let mut record_file = None;
+    let mut replay_file = None;
+    let mut gdb_port = None;
+    let mut argv = env::args();
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = argv.next().expect("Missing value for --quirks");
+                quirks = Quirks::preset(&name).unwrap_or_else(|| {
+                    panic!(
+                        "Unknown quirks preset: {} (expected one of {})",
+                        name,
+                        PRESETS.join(", ")
+                    )
+                });
+            }
+            "--tone" => {
+                let value = argv.next().expect("Missing value for --tone");
+                tone = value.parse().expect("Tone must be a frequency in Hz");
+            }
+            "--load-state" => {
+                state_file = Some(argv.next().expect("Missing value for --load-state"));
+            }
+            "--rewind" => {
+                let value = argv.next().expect("Missing value for --rewind");
+                rewind_seconds = value.parse().expect("Rewind must be a number of seconds");
+            }
+            "--seed" => {
+                let value = argv.next().expect("Missing value for --seed");
+                seed = Some(value.parse().expect("Seed must be a number"));
+            }
+            "--record" => {
+                record_file = Some(argv.next().expect("Missing value for --record"));
+            }
+            "--replay" => {
+                replay_file = Some(argv.next().expect("Missing value for --replay"));
+            }
+            "--gdb" => {
+                let value = argv.next().expect("Missing value for --gdb");
+                gdb_port = Some(value.parse().expect("Port must be a number"));
+            }
+            "--log" => {
+                let spec = argv.next().expect("Missing value for --log");
+                if let Err(e) = logger::init(&spec) {
+                    panic!("{}", e);
+                }
+            }
+            "--headless" => headless = true,
+            "--debug" => debug = true,
+            "--tui" => tui = true,
+            "--terminal" => in_terminal = true,
+            "--silent" => silent = true,
+            "--vip-timing" => vip_timing = true,
+            "--vip-memory" => vip_memory = true,
+            "--machine-code" => machine_code = true,
+            "--cycles" => {
+                let value = argv.next().expect("Missing value for --cycles");
+                let cycles = value.parse().expect("Cycles must be a number");
+                limit = Some(Limit::Cycles(cycles));
+            }
+            "--frames" => {
+                let value = argv.next().expect("Missing value for --frames");
+                let frames = value.parse().expect("Frames must be a number");
+                limit = Some(Limit::Frames(frames));
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    // `--vip-memory` and `--machine-code` apply on top of any quirks preset.
+    // Machine code needs the VIP memory layout as well.
+    if vip_memory || machine_code {
+        quirks.vip_memory = true;
+    }
+    if machine_code {
+        quirks.machine_code = true;
+    }
 
-    let args: Vec<String> = env::args().collect(); 
+    // `chip8 disasm <path_to_rom>` prints a listing of the ROM and exits.
+    if args.len() > 2 && args[1] == "disasm" {
+        let rom = match fs::read(&args[2]) {
+            Ok(file) => file,
+            Err(e) => panic!("Cound't load file: {}", e),
+        };
+        print!("{}", disasm::listing(&rom, &quirks));
+        return;
+    }
+
+    // `chip8 asm <path_to_source> <path_to_rom>` assembles a ROM and exits.
+    if args.len() > 3 && args[1] == "asm" {
+        let rom = match asm::assemble_file(Path::new(&args[2])) {
+            Ok(rom) => rom,
+            Err(e) => panic!("Couldn't assemble: {}", e),
+        };
+        if let Err(e) = fs::write(&args[3], &rom) {
+            panic!("Couldn't write file: {}", e);
+        }
+        return;
+    }
 
     // Read ROM
     let file_name = &args[1];
     let rom = match fs::read(file_name) {
         Ok(file) => file,
-        Err(e) => panic!("Cound't load file: {}", e.to_string()),
+        Err(e) => panic!("Cound't load file: {}", e),
+    };
+    let mut display_refresh_rate: f64 = if args.len() <= 2 {
+        480.0
+    } else {
+        args[2].parse::<f64>().unwrap()
     };
-    c8.load_ram(&rom);
-    println!("{:?}", rom);
-
-    // Setup Audio
-    let audio_device  = rodio::default_output_device().unwrap();
-    let audio_sink = Sink::new(&audio_device);
-    let audio_source = rodio::source::SineWave::new(440);
-    audio_sink.append(audio_source);
-    audio_sink.pause();
-
-    // Setup Window
-    let mut window = Window::new(
-        &format!("CHIP-8: {}", file_name),
-        WIDTH,
-        HEIGHT,
-        WindowOptions {
-            scale: Scale::X16,
-            ..WindowOptions::default()
-        },
-    )
-    .expect("Unable to open a Window");
-
-    // Using limit_update_rate minifb will check how much time has passed since
-    // the last time and if it's less than the selected time it will sleep for
-    // the remainder of it. This means that if more time has spent than the set
-    // time (external code taking longer) minifb will not do any waiting at all
-    // so there is no loss in CPU performance with this feature.
-    // Source: https://docs.rs/minifb/0.16.0/minifb/struct.Window.html#method.limit_update_rate
-    
-    let display_refresh_rate: f64 = if args.len() <= 2 { 480.0 } else { args[2].parse::<f64>().unwrap() };
-    let runloop_timer_default: usize = (display_refresh_rate / 60.0) as usize;
-    window.limit_update_rate(Some(std::time::Duration::from_secs_f64(1.0 / display_refresh_rate)));
-
-    let mut executing = true;
-    let mut wait_for_key: usize = 0;
-    let mut update_counter: usize = runloop_timer_default;
-
-    while window.is_open() && !window.is_key_down(Key::Escape) && c8.pc <= RAM_SIZE as u16 {
-        let mut key_press: [bool; 16] = [false; 16];
-
-        window.get_keys().map(|keys| {
-            for t in keys {
-                match t {
-                    Key::Key1 => key_press[0x1] = true,
-                    Key::Key2 => key_press[0x2] = true,
-                    Key::Key3 => key_press[0x3] = true,
-                    Key::Key4 => key_press[0xc] = true,
-                    Key::Q => key_press[0x4] = true,
-                    Key::W => key_press[0x5] = true,
-                    Key::E => key_press[0x6] = true,
-                    Key::R => key_press[0xd] = true,
-                    Key::A => key_press[0x7] = true,
-                    Key::S => key_press[0x8] = true,
-                    Key::D => key_press[0x9] = true,
-                    Key::F => key_press[0xe] = true,
-                    Key::Z => key_press[0xa] = true,
-                    Key::X => key_press[0x0] = true,
-                    Key::C => key_press[0xb] = true,
-                    Key::V => key_press[0xf] = true,
-                    _ => (),
-                }
-            }
-        });
 
+    // `--replay <path>` runs the ROM with the settings and keys of a movie.
+    let replay = replay_file.map(|path| {
+        let bytes = match fs::read(&path) {
+            Ok(file) => file,
+            Err(e) => panic!("Couldn't load file: {}", e),
+        };
+        match Movie::from_bytes(&bytes) {
+            Ok(movie) => movie,
+            Err(e) => panic!("Couldn't load movie: {}", e),
+        }
+    });
+    if replay.is_some() && (record_file.is_some() || state_file.is_some()) {
+        panic!("--replay cannot be combined with --record or --load-state");
+    }
+    if record_file.is_some() && (headless || state_file.is_some()) {
+        panic!("--record cannot be combined with --headless or --load-state");
+    }
+    if (debug || tui) && gdb_port.is_some() {
+        panic!("--debug and --tui cannot be combined with --gdb");
+    }
+    if (debug || tui) && vip_timing {
+        panic!("--debug and --tui cannot be combined with --vip-timing");
+    }
+    if (debug || tui) && (record_file.is_some() || replay.is_some()) {
+        panic!("--debug and --tui cannot be combined with --record or --replay");
+    }
+    // The random number generator is part of the save state, which would
+    // silently replace the seed.
+    if seed.is_some() && state_file.is_some() {
+        panic!("--seed cannot be combined with --load-state");
+    }
 
-        for j in 0..16 {
-            if key_press[j] {
-                if wait_for_key != 0 {
-                    executing = false;
-                    c8.v[wait_for_key] = j as u8;
-                    wait_for_key = 0;
-                    break;
-                }
+    let mut c8 = match &replay {
+        Some(movie) => {
+            display_refresh_rate = movie.cycles_per_frame as f64 * 60.0;
+            match movie.machine(&rom) {
+                Ok(c8) => c8,
+                Err(e) => panic!("Couldn't replay movie: {}", e),
+            }
+        }
+        None => {
+            let mut c8 = C8::new(quirks);
+            if let Err(e) = c8.load_ram(&rom) {
+                panic!("Couldn't load ROM: {}", e);
             }
+            c8
         }
+    };
 
-        if executing {
-            wait_for_key = c8.run(&key_press);
+    // `--load-state <path>` resumes from a save state of the same ROM.
+    if let Some(path) = state_file {
+        let state = match fs::read(&path) {
+            Ok(file) => file,
+            Err(e) => panic!("Couldn't load file: {}", e),
+        };
+        if let Err(e) = c8.load_state(&state) {
+            panic!("Couldn't load state: {}", e);
         }
+    }
 
-        // 60 Hz
-        if update_counter == 0 {
-            // The delay timer is active whenever the delay timer register (DT)
-            // is non-zero. This timer does nothing more than subtract 1 from
-            // the value of DT at a rate of 60Hz. When DT reaches 0, it
-            // deactivates.
-            if c8.dt > 0 {
-                c8.dt -= 1;
+    // `--seed <number>` makes RND produce the same numbers on every run. A
+    // movie always needs a seed, so recording picks one if none was given.
+    let cycles_per_frame = ((display_refresh_rate / 60.0) as u32).max(1);
+    let mut movie = replay.map(MovieMode::Replay);
+    if let Some(path) = record_file {
+        let seed = seed.unwrap_or_else(rand::random);
+        let mut recording = Movie::new(&rom, quirks, seed, cycles_per_frame);
+        recording.vip_timing = vip_timing;
+        movie = Some(MovieMode::Record(path, recording));
+    }
+    match &movie {
+        Some(MovieMode::Record(_, recording)) => c8.set_seed(recording.seed),
+        Some(MovieMode::Replay(_)) => (),
+        None => {
+            if let Some(seed) = seed {
+                c8.set_seed(seed);
             }
+        }
+    }
 
-            // The sound timer is active whenever the sound timer register (ST)
-            // is non-zero. This timer also decrements at a rate of 60Hz,
-            // however, as long as ST's value is greater than zero, the Chip-8
-            // buzzer will sound. When ST reaches zero, the sound timer
-            // deactivates.
-            // The sound produced by the Chip-8 interpreter has only one tone.
-            // The frequency of this tone is decided by the author of the
-            // interpreter.
-            if c8.st > 0 {
-                audio_sink.play();
-                c8.st -= 1;
-            } else if c8.st == 0 {
-                audio_sink.pause();
-            }
+    // The update rate is the number of instructions executed per second,
+    // unless `--vip-timing` times them like the COSMAC VIP did. Movies hold a
+    // whole number of instructions per frame, so every frame of a recording
+    // or replay runs the same number.
+    c8.set_speed(match &movie {
+        Some(MovieMode::Record(_, movie)) | Some(MovieMode::Replay(movie)) => movie.speed(),
+        None if vip_timing => Speed::Vip,
+        None => Speed::InstructionsPerSecond(display_refresh_rate.round() as u32),
+    });
+
+    // `--debug` runs the ROM from a command prompt in the terminal.
+    if debug {
+        debugger::run(c8, cycles_per_frame as u64);
+        return;
+    }
 
-            // Update Window
-            window
-                .update_with_buffer(&c8.display, WIDTH, HEIGHT)
-                .unwrap();
+    // `--tui` runs the ROM in a full screen debugger in the terminal.
+    if tui {
+        debug_in_terminal(c8, cycles_per_frame as u64);
+        return;
+    }
+
+    // `--gdb <port>` lets a debugger speaking the GDB remote protocol control
+    // the program, the frontends wait for it to connect before starting.
+    let mut gdb = gdb_port.map(|port| match GdbStub::listen(port) {
+        Ok(stub) => stub,
+        Err(e) => panic!("Couldn't start the gdb stub: {}", e),
+    });
 
-                update_counter = runloop_timer_default;
-        } else {
-            update_counter -= 1;
+    // `--headless` runs without a window or audio and prints the final state.
+    if headless {
+        let replay = match &movie {
+            Some(MovieMode::Replay(movie)) => Some(movie),
+            _ => None,
+        };
+        let limit = match (limit, replay) {
+            (Some(limit), _) => limit,
+            (None, Some(movie)) => Limit::Frames(movie.len() as u64),
+            (None, None) => panic!("--headless needs --cycles or --frames"),
+        };
+        let summary = headless::run(&mut c8, limit, replay, gdb.as_mut());
+        print!("{}", headless::dump(&c8, &summary));
+        if summary.error.is_some() || summary.desync.is_some() {
+            process::exit(1);
         }
+        return;
     }
+
+    println!("CHIP-8 Interpreter/Emulator");
+    let settings = Settings {
+        tone,
+        silent,
+        rewind_seconds,
+        movie,
+        gdb,
+    };
+    // `--terminal` plays in the terminal instead of a window.
+    if in_terminal {
+        play_in_terminal(c8, file_name, settings);
+    } else {
+        play(c8, file_name, settings);
+    }
+}
+
+#[cfg(feature = "frontend")]
+fn play(c8: C8, file_name: &str, settings: Settings) {
+    frontend::window::run(c8, file_name, settings);
+}
+
+#[cfg(not(feature = "frontend"))]
+fn play(_c8: C8, _file_name: &str, _settings: Settings) {
+    eprintln!("Built without the frontend feature, the window is not available");
+    process::exit(1);
+}
+
+#[cfg(feature = "terminal")]
+fn play_in_terminal(c8: C8, file_name: &str, settings: Settings) {
+    if let Err(e) = terminal::play::run(c8, file_name, settings) {
+        eprintln!("Terminal error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "terminal"))]
+fn play_in_terminal(_c8: C8, _file_name: &str, _settings: Settings) {
+    eprintln!("Built without the terminal feature, --terminal is not available");
+    process::exit(1);
+}
+
+#[cfg(feature = "terminal")]
+fn debug_in_terminal(c8: C8, cycles_per_frame: u64) {
+    if let Err(e) = terminal::debugger::run(c8, cycles_per_frame) {
+        eprintln!("Terminal error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "terminal"))]
+fn debug_in_terminal(_c8: C8, _cycles_per_frame: u64) {
+    eprintln!("Built without the terminal feature, --tui is not available");
+    process::exit(1);
 }
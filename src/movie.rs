@@ -0,0 +1,264 @@
+//! Recordings of the keys pressed during a run, which replay it exactly.
+//!
+//! A movie holds everything a run depends on besides the ROM: the quirks, the
+//! seed of the random number generator, the number of instructions executed
+//! per frame or VIP timing, and the keys held during every 60 Hz frame. Every
+//! `CHECKSUM_INTERVAL` frames a checksum of the whole machine is stored as
+//! well, so a replay that went differently (a desync) is noticed close to
+//! where it happened.
+
+use crate::c8::C8;
+use crate::error::C8Error;
+use crate::quirks::Quirks;
+use crate::state::{StateReader, StateWriter};
+use crate::timing::Speed;
+use std::{error, fmt};
+
+const MOVIE_MAGIC: [u8; 4] = *b"C8MV";
+// Checksums hash save states, so the version is bumped along with
+// `STATE_VERSION` as well.
+const MOVIE_VERSION: u16 = 1;
+
+/// Number of frames between two checksums of the machine.
+pub const CHECKSUM_INTERVAL: usize = 60;
+
+/// Errors raised while reading or replaying a movie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The file is corrupt, truncated or from an unsupported version.
+    Invalid { reason: &'static str },
+    /// The movie was recorded with a different ROM.
+    WrongRom,
+    /// The machine no longer matches the recording after `frame` frames.
+    Desync { frame: usize },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Invalid { reason } => write!(f, "Invalid movie: {}", reason),
+            MovieError::WrongRom => write!(f, "The movie was recorded with a different ROM"),
+            MovieError::Desync { frame } => write!(f, "Replay desynced at frame {}", frame),
+        }
+    }
+}
+
+impl error::Error for MovieError {}
+
+/// A recorded run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// Hash of the ROM the movie was recorded with.
+    pub rom_hash: u64,
+    /// Quirks the ROM was run with.
+    pub quirks: Quirks,
+    /// Seed of the random number generator.
+    pub seed: u64,
+    /// Instructions executed per 60 Hz frame.
+    pub cycles_per_frame: u32,
+    /// Whether the instructions were timed like on the COSMAC VIP instead.
+    pub vip_timing: bool,
+    // Keys held during each frame, bit k set for key k.
+    frames: Vec<u16>,
+    // Checksum after every CHECKSUM_INTERVAL frames.
+    checksums: Vec<u64>,
+}
+
+impl Movie {
+    /// Starts an empty recording of `rom` run with the given settings.
+    pub fn new(rom: &[u8], quirks: Quirks, seed: u64, cycles_per_frame: u32) -> Movie {
+        Movie {
+            rom_hash: hash(rom),
+            quirks,
+            seed,
+            cycles_per_frame,
+            vip_timing: false,
+            frames: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    /// A machine set up the way the recording started, with `rom` loaded.
+    pub fn machine(&self, rom: &[u8]) -> Result<C8, MovieError> {
+        if hash(rom) != self.rom_hash {
+            return Err(MovieError::WrongRom);
+        }
+        let mut c8 = C8::new(self.quirks);
+        // A ROM that fits normally can be too large for the VIP memory quirk.
+        if c8.load_ram(rom).is_err() {
+            return Err(MovieError::Invalid {
+                reason: "the ROM does not fit in memory with its quirks",
+            });
+        }
+        c8.set_seed(self.seed);
+        Ok(c8)
+    }
+
+    /// The speed the movie was recorded at. Movies hold a whole number of
+    /// instructions per frame, so a recording made at
+    /// `Speed::InstructionsPerSecond` has to run at
+    /// `Speed::InstructionsPerFrame` to replay exactly.
+    pub fn speed(&self) -> Speed {
+        if self.vip_timing {
+            Speed::Vip
+        } else {
+            Speed::InstructionsPerFrame(self.cycles_per_frame)
+        }
+    }
+
+    /// Number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no frame has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Appends a frame that ran with `keys` held and left the machine as `c8`.
+    pub fn record(&mut self, keys: &[bool; 16], c8: &C8) {
+        let mask = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, pressed)| **pressed)
+            .fold(0, |mask, (k, _)| mask | (1 << k));
+        self.frames.push(mask);
+        if self.frames.len().is_multiple_of(CHECKSUM_INTERVAL) {
+            self.checksums.push(checksum(c8));
+        }
+    }
+
+    /// Keys held during `frame`, or `None` past the end of the recording.
+    pub fn keys(&self, frame: usize) -> Option<[bool; 16]> {
+        let mask = self.frames.get(frame)?;
+        let mut keys = [false; 16];
+        for (k, pressed) in keys.iter_mut().enumerate() {
+            *pressed = mask & (1 << k) != 0;
+        }
+        Some(keys)
+    }
+
+    /// Checks `c8` against the recording after `frames` frames were replayed,
+    /// if a checksum was recorded at that point.
+    pub fn verify(&self, frames: usize, c8: &C8) -> Result<(), MovieError> {
+        if frames == 0 || !frames.is_multiple_of(CHECKSUM_INTERVAL) {
+            return Ok(());
+        }
+        match self.checksums.get(frames / CHECKSUM_INTERVAL - 1) {
+            Some(expected) if *expected != checksum(c8) => {
+                Err(MovieError::Desync { frame: frames })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Serializes the movie into its versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut movie = StateWriter::new();
+        movie.raw(&MOVIE_MAGIC);
+        movie.u16(MOVIE_VERSION);
+        movie.u64(self.rom_hash);
+        movie.quirks(&self.quirks);
+        movie.u64(self.seed);
+        movie.u32(self.cycles_per_frame);
+        movie.bool(self.vip_timing);
+        movie.u32(self.frames.len() as u32);
+        for mask in self.frames.iter() {
+            movie.u16(*mask);
+        }
+        movie.u32(self.checksums.len() as u32);
+        for checksum in self.checksums.iter() {
+            movie.u64(*checksum);
+        }
+        movie.finish()
+    }
+
+    /// Reads a movie written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        Movie::read(bytes).map_err(|e| match e {
+            C8Error::InvalidState { reason } => MovieError::Invalid { reason },
+            _ => MovieError::Invalid {
+                reason: "unreadable",
+            },
+        })
+    }
+
+    fn read(bytes: &[u8]) -> Result<Movie, C8Error> {
+        let invalid = |reason| Err(C8Error::InvalidState { reason });
+        let mut movie = StateReader::new(bytes);
+        if movie.raw(MOVIE_MAGIC.len())? != MOVIE_MAGIC {
+            return invalid("not a movie");
+        }
+        if movie.u16()? != MOVIE_VERSION {
+            return invalid("unsupported version");
+        }
+
+        let rom_hash = movie.u64()?;
+        let quirks = movie.quirks()?;
+        let seed = movie.u64()?;
+        let cycles_per_frame = movie.u32()?;
+        let vip_timing = movie.bool()?;
+        let mut frames = Vec::new();
+        for _ in 0..movie.u32()? {
+            frames.push(movie.u16()?);
+        }
+        let mut checksums = Vec::new();
+        for _ in 0..movie.u32()? {
+            checksums.push(movie.u64()?);
+        }
+        if checksums.len() != frames.len() / CHECKSUM_INTERVAL {
+            return invalid("checksums do not match the frames");
+        }
+        movie.finish()?;
+
+        Ok(Movie {
+            rom_hash,
+            quirks,
+            seed,
+            cycles_per_frame,
+            vip_timing,
+            frames,
+            checksums,
+        })
+    }
+}
+
+/// Checksum of the whole machine, equal for machines that will run alike.
+pub fn checksum(c8: &C8) -> u64 {
+    hash(&c8.save_state())
+}
+
+/// 64-bit FNV-1a hash of `bytes`, the same on every platform and build so
+/// results can be compared between runs.
+// Source: http://www.isthe.com/chongo/tech/comp/fnv/
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_too_large_for_the_quirks_is_an_error() {
+        let rom = vec![0; 0xD00];
+        let quirks = Quirks {
+            vip_memory: true,
+            ..Quirks::default()
+        };
+        let movie = Movie::new(&rom, quirks, 0, 8);
+        assert!(matches!(
+            movie.machine(&rom),
+            Err(MovieError::Invalid { .. })
+        ));
+        assert!(Movie::new(&rom, Quirks::default(), 0, 8)
+            .machine(&rom)
+            .is_ok());
+    }
+}
@@ -0,0 +1,197 @@
+// Several CHIP-8 instructions were implemented differently by the original
+// COSMAC VIP interpreter and by later interpreters such as CHIP-48 and
+// SUPER-CHIP, and programs written for one of them often misbehave on the
+// others. Each quirk below selects one of the behaviours.
+// Source: https://github.com/Chromatophore/HP48-Superchip
+// Source: https://chip-8.github.io/extensions/
+
+/// How `LD [I], Vx` (FX55) and `LD Vx, [I]` (FX65) change I.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoadStore {
+    /// I is left unchanged (SUPER-CHIP 1.1).
+    Unchanged,
+    /// I is incremented by x (CHIP-48).
+    IncrementByX,
+    /// I is incremented by x + 1, leaving it past the last register (COSMAC
+    /// VIP, XO-CHIP).
+    IncrementByXPlusOne,
+}
+
+/// Behaviour of the instructions whose meaning differs between interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// `SHR`/`SHL` (8XY6/8XYE) shift Vy and store the result in Vx, instead of
+    /// shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// How FX55/FX65 change I.
+    pub load_store: LoadStore,
+    /// `JP V0, addr` (BNNN) is read as BXNN and jumps to XNN + Vx instead of
+    /// NNN + V0.
+    pub jump_uses_vx: bool,
+    /// `OR`, `AND` and `XOR` (8XY1-8XY3) reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    /// around to the opposite side. The starting position always wraps.
+    pub clip_sprites: bool,
+    /// `DRW` waits for the next 60 Hz display interrupt, so at most one sprite
+    /// is drawn per frame.
+    pub display_wait: bool,
+    /// Enables the XO-CHIP extensions: 64 KiB of memory, `LD I, long`
+    /// (F000 NNNN), register range loads and stores (5XY2/5XY3), bitplanes
+    /// (FN01) and scrolling up (00DN).
+    pub xo_chip: bool,
+    /// `LD Vx, K` (FX0A) stores the key once it is released again, instead of
+    /// as soon as it is pressed.
+    pub wait_for_release: bool,
+    /// The stack, V0-VF and the display live in RAM at 0xEA0-0xFFF like on
+    /// the COSMAC VIP, where programs can read and change them, and the
+    /// stack holds only 12 addresses.
+    pub vip_memory: bool,
+    /// `SYS addr` (0NNN) runs the CDP1802 machine code at addr until it
+    /// returns with D4, as on the COSMAC VIP, instead of ignoring it. Machine
+    /// code expects the VIP's memory layout, so this implies `vip_memory`.
+    pub machine_code: bool,
+}
+
+/// Names accepted by `Quirks::preset`.
+pub const PRESETS: [&str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+impl Quirks {
+    /// The original CHIP-8 interpreter on the RCA COSMAC VIP.
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store: LoadStore::IncrementByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+            xo_chip: false,
+            wait_for_release: true,
+            vip_memory: false,
+            machine_code: false,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store: LoadStore::IncrementByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            xo_chip: false,
+            wait_for_release: false,
+            vip_memory: false,
+            machine_code: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP-48 calculators.
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            xo_chip: false,
+            wait_for_release: false,
+            vip_memory: false,
+            machine_code: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store: LoadStore::IncrementByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+            xo_chip: true,
+            wait_for_release: true,
+            vip_memory: false,
+            machine_code: false,
+        }
+    }
+
+    /// Looks up a preset by one of the names in `PRESETS`.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    // The behaviour this interpreter has always had: shifts and loads/stores
+    // from CHIP-48/SUPER-CHIP, jumps and flags from the VIP, and sprites that
+    // wrap around the screen.
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+            xo_chip: false,
+            wait_for_release: false,
+            vip_memory: false,
+            machine_code: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_found_by_name() {
+        for name in PRESETS.iter() {
+            assert!(Quirks::preset(name).is_some(), "{}", name);
+        }
+        assert_eq!(Quirks::preset("VIP"), Some(Quirks::vip()));
+        assert_eq!(Quirks::preset("xochip"), Some(Quirks::xo_chip()));
+        assert_eq!(Quirks::preset("chip-8"), None);
+    }
+
+    #[test]
+    fn presets_match_their_interpreters() {
+        let vip = Quirks::vip();
+        assert!(vip.shift_uses_vy && vip.logic_resets_vf && vip.display_wait);
+        assert_eq!(vip.load_store, LoadStore::IncrementByXPlusOne);
+        assert!(!vip.jump_uses_vx && !vip.xo_chip);
+
+        let chip48 = Quirks::chip48();
+        assert_eq!(chip48.load_store, LoadStore::IncrementByX);
+        assert!(chip48.jump_uses_vx && !chip48.shift_uses_vy);
+
+        let schip = Quirks::schip();
+        assert_eq!(schip.load_store, LoadStore::Unchanged);
+        assert!(schip.jump_uses_vx && schip.clip_sprites && !schip.display_wait);
+
+        let xo_chip = Quirks::xo_chip();
+        assert!(xo_chip.xo_chip && xo_chip.shift_uses_vy && !xo_chip.clip_sprites);
+        assert!(!xo_chip.jump_uses_vx && !xo_chip.logic_resets_vf);
+
+        // No preset needs the VIP's memory layout or machine code.
+        for name in PRESETS.iter() {
+            let quirks = Quirks::preset(name).unwrap();
+            assert!(!quirks.vip_memory && !quirks.machine_code, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// The random number generator behind `RND Vx, byte` (CXKK). It is owned by
+// the machine and seedable, so a run can be reproduced exactly and its state
+// is part of save states.
+//
+// There is no mode that reproduces the numbers of the COSMAC VIP. Its
+// interpreter derived them from its own 1802 code and registers, and that
+// interpreter is not part of this emulator, so an imitation could not be
+// checked against the hardware.
+// Programs that need particular numbers should get them from a seed.
+
+/// A xorshift64* pseudorandom number generator.
+// Source: https://vigna.di.unimi.it/ftp/papers/xorshift.pdf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator that always produces the same numbers for the same
+    /// `seed`.
+    pub fn new(seed: u64) -> Rng {
+        // Spread the seed over all 64 bits with a SplitMix64 step, small seeds
+        // would otherwise start with mostly zero bits and xorshift must never
+        // be in the all zero state.
+        // Source: https://prng.di.unimi.it/splitmix64.c
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Rng {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    /// The next random byte.
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        // The high bits of the multiplied state are the most random.
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+
+    // The state is saved as is, a restored generator continues where the
+    // saved one left off.
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn from_state(state: u64) -> Option<Rng> {
+        if state == 0 {
+            None
+        } else {
+            Some(Rng { state })
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// The frame loop the playing frontends share: the keys of every frame come
+// from a replayed movie or the keyboard, they are recorded into a movie and
+// replays are checked for desyncs, and the gdb stub can stop the program
+// between instructions.
+
+use crate::gdb::GdbStub;
+use crate::MovieMode;
+use chip8::timing::Scheduler;
+use chip8::{C8Error, C8};
+use std::fs;
+
+/// The movie and remote debugger of a frontend playing a program.
+pub struct Session {
+    movie: Option<MovieMode>,
+    gdb: Option<GdbStub>,
+    // Frames run in total.
+    frame: usize,
+    // Message for the user about the movie, shown by the frontend.
+    notice: Option<String>,
+}
+
+impl Session {
+    pub fn new(movie: Option<MovieMode>, gdb: Option<GdbStub>) -> Session {
+        Session {
+            movie,
+            gdb,
+            frame: 0,
+            notice: None,
+        }
+    }
+
+    /// Whether a movie is being recorded or replayed. Rewinding and loading
+    /// states are not recorded in movies, so frontends disable them.
+    pub fn has_movie(&self) -> bool {
+        self.movie.is_some()
+    }
+
+    /// Runs a frame with `keyboard` held, or the keys recorded for it while a
+    /// movie is replayed. Returns false when the debugger stopped the program
+    /// before the frame was finished, the program and its timers are then
+    /// stopped.
+    pub fn run_frame(
+        &mut self,
+        scheduler: &mut Scheduler,
+        c8: &mut C8,
+        keyboard: [bool; 16],
+    ) -> Result<bool, C8Error> {
+        // Keys are read once per frame, so a frame can be replayed from the
+        // keys recorded for it.
+        let key_press = match &self.movie {
+            Some(MovieMode::Replay(replay)) => match replay.keys(self.frame) {
+                Some(keys) => keys,
+                None => {
+                    self.notice = Some("Replay finished, the keyboard takes over".to_string());
+                    self.movie = None;
+                    keyboard
+                }
+            },
+            _ => keyboard,
+        };
+
+        let completed = match &mut self.gdb {
+            Some(gdb) => scheduler.run_frame_with(c8, &key_press, |c8| gdb.poll(c8))?,
+            None => scheduler.run_frame(c8, &key_press).map(|()| true)?,
+        };
+        if !completed {
+            return Ok(false);
+        }
+        self.frame += 1;
+
+        match &mut self.movie {
+            Some(MovieMode::Record(_, recording)) => recording.record(&key_press, c8),
+            Some(MovieMode::Replay(replay)) => {
+                if let Err(e) = replay.verify(self.frame, c8) {
+                    self.notice = Some(format!("{}, the keyboard takes over", e));
+                    self.movie = None;
+                }
+            }
+            None => (),
+        }
+        Ok(true)
+    }
+
+    /// The message about the movie left by the last frames, if any.
+    pub fn notice(&mut self) -> Option<String> {
+        self.notice.take()
+    }
+
+    /// Writes the movie to its path if it is a recording.
+    pub fn finish(self) {
+        if let Some(MovieMode::Record(path, recording)) = self.movie {
+            match fs::write(&path, recording.to_bytes()) {
+                Ok(()) => println!("Saved movie of {} frames to {}", recording.len(), path),
+                Err(e) => println!("Couldn't save movie: {}", e),
+            }
+        }
+    }
+}
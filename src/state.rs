@@ -0,0 +1,160 @@
+// Reading and writing the fields of a save state. Numbers are stored little
+// endian and variable length fields are preceded by their length.
+
+use crate::error::C8Error;
+use crate::quirks::{LoadStore, Quirks};
+
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> StateWriter {
+        StateWriter { bytes: Vec::new() }
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    // Fixed size fields, their length is implied by the version.
+    pub(crate) fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.raw(bytes);
+    }
+
+    pub(crate) fn quirks(&mut self, quirks: &Quirks) {
+        self.bool(quirks.shift_uses_vy);
+        self.u8(match quirks.load_store {
+            LoadStore::Unchanged => 0,
+            LoadStore::IncrementByX => 1,
+            LoadStore::IncrementByXPlusOne => 2,
+        });
+        self.bool(quirks.jump_uses_vx);
+        self.bool(quirks.logic_resets_vf);
+        self.bool(quirks.clip_sprites);
+        self.bool(quirks.display_wait);
+        self.bool(quirks.xo_chip);
+        self.bool(quirks.wait_for_release);
+        self.bool(quirks.vip_memory);
+        self.bool(quirks.machine_code);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn raw(&mut self, len: usize) -> Result<&'a [u8], C8Error> {
+        if self.bytes.len() - self.pos < len {
+            return Err(C8Error::InvalidState {
+                reason: "unexpected end of data",
+            });
+        }
+        let raw = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(raw)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, C8Error> {
+        Ok(self.raw(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, C8Error> {
+        let raw = self.raw(2)?;
+        Ok(u16::from_le_bytes([raw[0], raw[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, C8Error> {
+        let raw = self.raw(4)?;
+        Ok(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, C8Error> {
+        let mut raw = [0; 8];
+        raw.copy_from_slice(self.raw(8)?);
+        Ok(u64::from_le_bytes(raw))
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, C8Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(C8Error::InvalidState {
+                reason: "invalid flag",
+            }),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], C8Error> {
+        let len = self.u32()? as usize;
+        self.raw(len)
+    }
+
+    pub(crate) fn quirks(&mut self) -> Result<Quirks, C8Error> {
+        let shift_uses_vy = self.bool()?;
+        let load_store = match self.u8()? {
+            0 => LoadStore::Unchanged,
+            1 => LoadStore::IncrementByX,
+            2 => LoadStore::IncrementByXPlusOne,
+            _ => {
+                return Err(C8Error::InvalidState {
+                    reason: "unknown load/store quirk",
+                })
+            }
+        };
+        Ok(Quirks {
+            shift_uses_vy,
+            load_store,
+            jump_uses_vx: self.bool()?,
+            logic_resets_vf: self.bool()?,
+            clip_sprites: self.bool()?,
+            display_wait: self.bool()?,
+            xo_chip: self.bool()?,
+            wait_for_release: self.bool()?,
+            vip_memory: self.bool()?,
+            machine_code: self.bool()?,
+        })
+    }
+
+    // Fails unless every byte was read, so truncated or padded states are not
+    // silently accepted.
+    pub(crate) fn finish(self) -> Result<(), C8Error> {
+        if self.pos != self.bytes.len() {
+            return Err(C8Error::InvalidState {
+                reason: "trailing data",
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+// A full screen debugger: the display next to the registers and the stack,
+// with the disassembly around the program counter and a memory view below,
+// driven by single keys. The program runs at its normal speed between
+// breakpoints and takes its keypad input from the keyboard while it runs.
+
+use super::{display_lines, Keypad, Screen};
+use crate::debugger::{describe, describe_key_wait, disassemble, instruction_length, RUN_LIMIT};
+use chip8::debug::{Debugger, Stop};
+use chip8::timing::FRAME;
+use chip8::C8;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::Attribute;
+use std::time::Instant;
+
+// Rows of the disassembly and of the memory view, and instructions shown
+// before the selected one.
+const LIST_ROWS: usize = 12;
+const LIST_BEFORE: u16 = 4;
+
+// Column where the panels on the right start.
+const RIGHT: usize = 68;
+
+const HELP: &str = "F5/c run  Esc/Space pause  F11/s step  F10/n next  f finish  \
+                    F9/b breakpoint  Up/Down select  PgUp/PgDn memory  i memory at I  q quit";
+
+struct Tui {
+    c8: C8,
+    debugger: Debugger,
+    cycles_per_frame: u64,
+    keypad: Keypad,
+    running: bool,
+    status: String,
+    // Address of the instruction selected in the disassembly, breakpoints are
+    // set there.
+    selected: u16,
+    // First address shown in the memory view.
+    memory: u16,
+    quit: bool,
+}
+
+/// Runs the debugger until it is quit with q or Ctrl-C. The program starts
+/// paused.
+pub fn run(c8: C8, cycles_per_frame: u64) -> crossterm::Result<()> {
+    let mut screen = Screen::new()?;
+    let mut tui = Tui {
+        selected: c8.pc,
+        memory: c8.i() & !0xF,
+        c8,
+        debugger: Debugger::new(cycles_per_frame),
+        cycles_per_frame,
+        keypad: Keypad::new(),
+        running: false,
+        status: "Paused".to_string(),
+        quit: false,
+    };
+
+    loop {
+        screen.draw(&tui.rows())?;
+
+        // Keys are handled as they arrive until the next frame is due.
+        let deadline = Instant::now() + FRAME;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+            if let Event::Key(key) = event::read()? {
+                tui.key(key);
+            }
+            if tui.quit {
+                return Ok(());
+            }
+        }
+
+        if tui.running {
+            let stop = tui
+                .debugger
+                .cont(&mut tui.c8, &tui.keypad.keys(), tui.cycles_per_frame);
+            if stop != Stop::Limit {
+                tui.stopped(stop);
+            }
+        }
+        tui.keypad.frame();
+    }
+}
+
+impl Tui {
+    fn key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+
+        // While the program runs the keyboard is its keypad.
+        if self.running {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char(' ') => self.stopped(Stop::Step),
+                KeyCode::F(9) => self.toggle_breakpoint(),
+                KeyCode::Char(c) => {
+                    self.keypad.press(c);
+                }
+                _ => (),
+            }
+            return;
+        }
+
+        let keys = self.keypad.keys();
+        match key.code {
+            KeyCode::F(5) | KeyCode::Char('c') => {
+                self.running = true;
+                self.status = "Running".to_string();
+            }
+            KeyCode::F(11) | KeyCode::Char('s') => {
+                let stop = self.debugger.step(&mut self.c8, &keys, 1);
+                self.stopped(stop);
+            }
+            KeyCode::F(10) | KeyCode::Char('n') => {
+                let stop = self.debugger.next(&mut self.c8, &keys, RUN_LIMIT);
+                self.stopped(stop);
+            }
+            KeyCode::Char('f') => {
+                let stop = self.debugger.finish(&mut self.c8, &keys, RUN_LIMIT);
+                self.stopped(stop);
+            }
+            KeyCode::F(9) | KeyCode::Char('b') => self.toggle_breakpoint(),
+            KeyCode::Up => self.selected = self.selected.saturating_sub(2),
+            KeyCode::Down => {
+                self.selected = self
+                    .selected
+                    .saturating_add(instruction_length(&self.c8, self.selected))
+            }
+            KeyCode::PageUp => {
+                self.memory = self.memory.saturating_sub(16 * LIST_ROWS as u16);
+            }
+            KeyCode::PageDown => {
+                let last = (self.c8.ram().len() - 16) as u16;
+                self.memory = self.memory.saturating_add(16 * LIST_ROWS as u16).min(last);
+            }
+            KeyCode::Char('i') => self.memory = self.c8.i() & !0xF,
+            KeyCode::Char('q') => self.quit = true,
+            _ => (),
+        }
+    }
+
+    fn stopped(&mut self, stop: Stop) {
+        self.running = false;
+        self.selected = self.c8.pc;
+        self.status = describe(&self.c8, &stop).unwrap_or_else(|| "Paused".to_string());
+    }
+
+    fn toggle_breakpoint(&mut self) {
+        if !self.debugger.add_breakpoint(self.selected) {
+            self.debugger.remove_breakpoint(self.selected);
+        }
+    }
+
+    fn rows(&self) -> Vec<String> {
+        let c8 = &self.c8;
+        let mut rows = vec![
+            format!(
+                "CHIP-8 debugger  {}  ({} instructions)",
+                self.status,
+                self.debugger.cycles()
+            ),
+            String::new(),
+        ];
+
+        // The display in a frame, with the registers and the stack to its
+        // right.
+        let mut left = vec![format!("┌{}┐", "─".repeat(64))];
+        left.extend(display_lines(c8).iter().map(|line| format!("│{}│", line)));
+        left.push(format!("└{}┘", "─".repeat(64)));
+
+        let mut right = vec!["Registers".to_string()];
+        for (j, regs) in c8.v.chunks(4).enumerate() {
+            let regs: Vec<String> = regs
+                .iter()
+                .enumerate()
+                .map(|(k, v)| format!("V{:X} {:02X}", j * 4 + k, v))
+                .collect();
+            right.push(regs.join("  "));
+        }
+        right.push(format!("I  {:#05x}  PC {:#05x}", c8.i(), c8.pc));
+        right.push(format!("DT {:#04x}   ST {:#04x}", c8.dt, c8.st));
+        right.push(c8.key_wait().map(describe_key_wait).unwrap_or_default());
+        right.push(String::new());
+        right.push(format!("Stack ({})", c8.stack().len()));
+        for addrs in c8.stack().chunks(4) {
+            let addrs: Vec<String> = addrs.iter().map(|a| format!("{:#05x}", a)).collect();
+            right.push(addrs.join("  "));
+        }
+
+        for j in 0..left.len().max(right.len()) {
+            let left = left.get(j).map(String::as_str).unwrap_or("");
+            let right = right.get(j).map(String::as_str).unwrap_or("");
+            rows.push(format!("{}{}", pad(left, RIGHT), right));
+        }
+        rows.push(String::new());
+
+        // The disassembly around the selected instruction and the memory
+        // view.
+        rows.push(format!("{}{}", pad("Disassembly", RIGHT - 28), "Memory"));
+        let mut addr = self.selected.saturating_sub(2 * LIST_BEFORE);
+        let ram = c8.ram();
+        for j in 0..LIST_ROWS {
+            let marker = if addr == c8.pc { '>' } else { ' ' };
+            let breakpoint = if self.debugger.breakpoints().any(|b| b == addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let line = pad(
+                &format!(
+                    "{}{} {:#05x}  {}",
+                    marker,
+                    breakpoint,
+                    addr,
+                    disassemble(c8, addr)
+                ),
+                RIGHT - 28,
+            );
+            let line = if addr == self.selected {
+                format!("{}{}{}", Attribute::Reverse, line, Attribute::Reset)
+            } else {
+                line
+            };
+
+            let start = self.memory as usize + j * 16;
+            let memory = match ram.get(start..start + 16) {
+                Some(bytes) => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    format!("{:#05x}: {}", start, bytes.join(" "))
+                }
+                None => String::new(),
+            };
+            rows.push(format!("{}{}", line, memory));
+            addr = addr.saturating_add(instruction_length(c8, addr));
+        }
+
+        rows.push(String::new());
+        rows.push(HELP.to_string());
+        rows
+    }
+}
+
+// `text` followed by spaces to make it `width` characters long.
+fn pad(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    format!("{}{}", text, " ".repeat(width.saturating_sub(len)))
+}
@@ -0,0 +1,171 @@
+// Frontends drawn in a terminal with crossterm, for machines where no window
+// can be opened such as over SSH.
+
+pub mod debugger;
+pub mod play;
+
+use chip8::C8;
+use crossterm::style::Print;
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use std::io::{self, Stdout, Write};
+
+// Terminals report key presses but not releases, so a key counts as held for
+// this many frames after it was last pressed. It is long enough to bridge
+// the delay before the terminal starts repeating a key held down.
+const KEY_HOLD_FRAMES: u8 = 15;
+
+/// The terminal in raw mode on the alternate screen. The terminal is restored
+/// when it is dropped, also when unwinding from a panic.
+pub struct Screen {
+    out: Stdout,
+}
+
+impl Screen {
+    pub fn new() -> crossterm::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(ClearType::All)
+        )?;
+        Ok(Screen { out })
+    }
+
+    /// Replaces the contents of the screen with `rows`, from the top left
+    /// corner.
+    pub fn draw(&mut self, rows: &[String]) -> crossterm::Result<()> {
+        for (y, row) in rows.iter().enumerate() {
+            queue!(
+                self.out,
+                cursor::MoveTo(0, y as u16),
+                Print(row),
+                terminal::Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        queue!(self.out, terminal::Clear(ClearType::FromCursorDown))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The keys of the hexadecimal keypad held down.
+pub struct Keypad {
+    // Frames left until each key counts as released.
+    held: [u8; 16],
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad { held: [0; 16] }
+    }
+
+    /// Presses the keypad key mapped to `c`, returning whether there is one.
+    pub fn press(&mut self, c: char) -> bool {
+        match keypad_key(c) {
+            Some(key) => {
+                self.held[key] = KEY_HOLD_FRAMES;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lets a frame pass, releasing keys that weren't pressed again.
+    pub fn frame(&mut self) {
+        for frames in self.held.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+    }
+
+    pub fn keys(&self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (pressed, frames) in keys.iter_mut().zip(self.held.iter()) {
+            *pressed = *frames > 0;
+        }
+        keys
+    }
+}
+
+// The same layout as the window:
+//  1 2 3 C        1 2 3 4
+//  4 5 6 D  --->  Q W E R
+//  7 8 9 E        A S D F
+//  A 0 B F        Z X C V
+fn keypad_key(c: char) -> Option<usize> {
+    let key = match c.to_ascii_lowercase() {
+        '1' => 0x1,
+        '2' => 0x2,
+        '3' => 0x3,
+        '4' => 0xc,
+        'q' => 0x4,
+        'w' => 0x5,
+        'e' => 0x6,
+        'r' => 0xd,
+        'a' => 0x7,
+        's' => 0x8,
+        'd' => 0x9,
+        'f' => 0xe,
+        'z' => 0xa,
+        'x' => 0x0,
+        'c' => 0xb,
+        'v' => 0xf,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The display as 16 lines of 64 characters. The 64x32 display is drawn with
+/// half blocks, two pixels to a character, and the 128x64 display with
+/// braille patterns, eight pixels to a character. XO-CHIP bitplanes are not
+/// told apart, any colour but the background is drawn.
+pub fn display_lines(c8: &C8) -> Vec<String> {
+    let width = c8.width();
+    let pixels = c8.pixels();
+    let on = |x: usize, y: usize| pixels[y * width + x] != 0;
+
+    if c8.is_hires() {
+        // Bits of the braille dots, in a 2x4 cell.
+        // Source: https://en.wikipedia.org/wiki/Braille_Patterns
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        (0..c8.height() / 4)
+            .map(|row| {
+                (0..width / 2)
+                    .map(|col| {
+                        let mut bits = 0;
+                        for (dy, dots) in DOTS.iter().enumerate() {
+                            for (dx, dot) in dots.iter().enumerate() {
+                                if on(col * 2 + dx, row * 4 + dy) {
+                                    bits |= dot;
+                                }
+                            }
+                        }
+                        std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+                    })
+                    .collect()
+            })
+            .collect()
+    } else {
+        (0..c8.height() / 2)
+            .map(|row| {
+                (0..width)
+                    .map(|x| match (on(x, row * 2), on(x, row * 2 + 1)) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
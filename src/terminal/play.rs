@@ -0,0 +1,97 @@
+// Plays a ROM in the terminal: the display is redrawn at 60 Hz with half
+// blocks or braille and the keyboard is read in raw mode. The sound timer
+// rings the terminal bell.
+
+use super::{display_lines, Keypad, Screen};
+use crate::session::Session;
+use crate::Settings;
+use chip8::timing::Scheduler;
+use chip8::C8;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use log::info;
+use std::io::{self, Write};
+use std::time::Instant;
+
+/// Plays the program loaded into `c8` until Escape or Ctrl-C is pressed or
+/// the program exits.
+pub fn run(mut c8: C8, file_name: &str, settings: Settings) -> crossterm::Result<()> {
+    let mut screen = Screen::new()?;
+    let mut keypad = Keypad::new();
+    let mut scheduler = Scheduler::new();
+    let mut session = Session::new(settings.movie, settings.gdb);
+    let mut status = format!("CHIP-8: {}  (1234 QWER ASDF ZXCV, Esc quits)", file_name);
+    let mut drawn = Vec::new();
+    let mut sounding = false;
+    let mut error = None;
+
+    'frames: while !c8.has_exited() {
+        // The screen is only redrawn when something changed, which keeps
+        // the output small over slow connections.
+        let mut rows = vec![status.clone(), format!("┌{}┐", "─".repeat(64))];
+        rows.extend(display_lines(&c8).iter().map(|line| format!("│{}│", line)));
+        rows.push(format!("└{}┘", "─".repeat(64)));
+        if rows != drawn {
+            screen.draw(&rows)?;
+            drawn = rows;
+        }
+
+        // Keys are read until the next frame is due.
+        let deadline = Instant::now() + scheduler.until_next_frame();
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+            if let Event::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Esc => break 'frames,
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        break 'frames
+                    }
+                    KeyCode::Char(c) => {
+                        keypad.press(c);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        for _ in 0..scheduler.frames_due() {
+            keypad.frame();
+
+            // While the debugger has control the program and its timers are
+            // stopped.
+            let completed = session.run_frame(&mut scheduler, &mut c8, keypad.keys());
+            if let Some(notice) = session.notice() {
+                status = notice;
+            }
+            match completed {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => {
+                    error = Some(e);
+                    break 'frames;
+                }
+            }
+            // The bell rings once at the start of every sound, terminals
+            // can't hold a tone.
+            if c8.st > 0 && !sounding && !settings.silent {
+                print!("\x07");
+                let _ = io::stdout().flush();
+            }
+            sounding = c8.st > 0;
+
+            if c8.has_exited() {
+                break;
+            }
+        }
+    }
+
+    // Messages are printed once the terminal is restored.
+    drop(screen);
+    info!(target: "timing", "{}", scheduler.stats());
+    if let Some(e) = error {
+        println!("Error: {}", e);
+    }
+    session.finish();
+    Ok(())
+}
@@ -0,0 +1,275 @@
+//! Runs a machine in real time, independently of how often a frontend gets
+//! to draw.
+//!
+//! `C8::run_frame` executes one 60 Hz frame of the program at the speed set
+//! with `C8::set_speed`. `Scheduler` measures the wall clock to tell a
+//! frontend how many frames are due, so the timers tick exactly 60 times a
+//! second even when display updates stall or come faster, and keeps
+//! statistics of how long the frames took to run.
+//!
+//! With `Speed::Vip` a frame runs as many instructions as fit into the time
+//! the COSMAC VIP had for them, each instruction costing what it did on the
+//! VIP, for programs that rely on the speed of the original hardware.
+
+use crate::c8::C8;
+use crate::error::C8Error;
+use crate::instruction::Instruction;
+use log::debug;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// The delay and sound timers count down at a rate of 60Hz.
+// Source: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+/// Length of a frame, one tick of the timers.
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// After a stall, at most this many frames are run to catch up with the wall
+// clock. Frames beyond it are dropped, so the program slows down instead of
+// running in a burst.
+const MAX_CATCH_UP: u32 = 6;
+
+// The VIP's CDP1802 runs at 1.76 MHz and takes 8 clock cycles for a machine
+// cycle, so 3668 machine cycles pass in a 60 Hz frame. The CDP1861 display
+// steals 1024 of them to fetch 128 lines of 8 bytes by DMA, and the interrupt
+// routine that starts it and counts down the timers takes about 30 more. The
+// rest runs the interpreter.
+// Source: RCA CDP1861 Video Display Controller datasheet
+pub(crate) const VIP_FRAME_CYCLES: u32 = 3668 - 1024 - 30;
+
+/// How many instructions `C8::run_frame` executes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Speed {
+    /// The same number of instructions in every frame.
+    InstructionsPerFrame(u32),
+    /// A number of instructions per second. Rates that are not a multiple of
+    /// 60 are spread over the frames, so no instructions are lost to
+    /// rounding.
+    InstructionsPerSecond(u32),
+    /// Every instruction costs the machine cycles it took on the COSMAC VIP,
+    /// `DRW` waits for the display interrupt and a frame ends once the
+    /// cycles the VIP had between two interrupts are spent.
+    Vip,
+}
+
+impl Default for Speed {
+    // 480 instructions per second, the default update rate.
+    fn default() -> Speed {
+        Speed::InstructionsPerFrame(8)
+    }
+}
+
+/// How long frames took to run on the host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Frames run.
+    pub frames: u64,
+    /// Frames skipped because the host fell behind the wall clock.
+    pub dropped: u64,
+    /// Total time spent running frames.
+    pub busy: Duration,
+    /// Longest time a frame took to run.
+    pub worst: Duration,
+}
+
+impl FrameStats {
+    /// Average time a frame took to run.
+    pub fn average(&self) -> Duration {
+        if self.frames == 0 {
+            return Duration::from_secs(0);
+        }
+        // A u32 of frames covers more than two years of running.
+        self.busy / self.frames.min(u32::MAX as u64) as u32
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} frames ({} dropped), {:.2} ms average, {:.2} ms worst of {:.2} ms",
+            self.frames,
+            self.dropped,
+            self.average().as_secs_f64() * 1000.0,
+            self.worst.as_secs_f64() * 1000.0,
+            FRAME.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Paces frames by the wall clock.
+pub struct Scheduler {
+    last: Instant,
+    // Time passed that hasn't been run as frames yet.
+    behind: Duration,
+    stats: FrameStats,
+}
+
+impl Scheduler {
+    /// Starts counting time from now.
+    pub fn new() -> Scheduler {
+        Scheduler {
+            last: Instant::now(),
+            behind: Duration::from_secs(0),
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Number of frames to run since the last call to keep up with the wall
+    /// clock.
+    pub fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+        self.behind += now - self.last;
+        self.last = now;
+
+        let mut due = 0;
+        while self.behind >= FRAME {
+            self.behind -= FRAME;
+            due += 1;
+        }
+        if due > MAX_CATCH_UP {
+            debug!(target: "timing", "Dropped {} frames", due - MAX_CATCH_UP);
+            self.stats.dropped += (due - MAX_CATCH_UP) as u64;
+            due = MAX_CATCH_UP;
+        }
+        due
+    }
+
+    /// Time left until the next frame is due.
+    pub fn until_next_frame(&self) -> Duration {
+        FRAME
+            .checked_sub(self.behind + self.last.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Runs a frame with `C8::run_frame`, timing it.
+    pub fn run_frame(&mut self, c8: &mut C8, key_press: &[bool; 16]) -> Result<(), C8Error> {
+        self.run_frame_with(c8, key_press, |_| true).map(|_| ())
+    }
+
+    /// Runs a frame with `C8::run_frame_with`, timing it. Frames ended early
+    /// by `before` are not counted.
+    pub fn run_frame_with<F>(
+        &mut self,
+        c8: &mut C8,
+        key_press: &[bool; 16],
+        before: F,
+    ) -> Result<bool, C8Error>
+    where
+        F: FnMut(&mut C8) -> bool,
+    {
+        let start = Instant::now();
+        let completed = c8.run_frame_with(key_press, before)?;
+        if completed {
+            let took = start.elapsed();
+            self.stats.frames += 1;
+            self.stats.busy += took;
+            self.stats.worst = self.stats.worst.max(took);
+        }
+        Ok(completed)
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+// Machine cycles the VIP interpreter takes to fetch, decode and execute an
+// instruction, from the times measured in microseconds by Jackson S at 4.54
+// microseconds per machine cycle. Skips cost more when they are taken. `DRW`
+// is charged for drawing only, as it waits for the interrupt on its own.
+// The time to draw depends on the sprite and is an estimate here, as is the
+// cost of the SUPER-CHIP and XO-CHIP instructions the VIP never had, which
+// are charged like the VIP instructions closest to them. FX55 and FX65 loop
+// over the registers, so instead of their average of 133 cycles they are
+// charged an estimated 14 cycles per register on top of 4 to set up.
+// Source: https://jackson-s.me/2019/07/13/Chip-8-Instruction-Scheduling-and-Frequency.html
+pub(crate) fn vip_cycles(instruction: Instruction, skipped: bool) -> u32 {
+    let skip = |taken, not_taken| if skipped { taken } else { not_taken };
+    let registers = |count: usize| 4 + 14 * count as u32;
+    match instruction {
+        Instruction::Cls => 24,
+        Instruction::Ret | Instruction::Jump(_) | Instruction::Call(_) => 23,
+        Instruction::JumpOffset(_) | Instruction::Sys(_) => 23,
+        Instruction::SkipEqByte(..) | Instruction::SkipNeByte(..) => skip(12, 10),
+        Instruction::SkipEqReg(..) | Instruction::SkipNeReg(..) => skip(16, 14),
+        Instruction::SkipKey(_) | Instruction::SkipNotKey(_) => skip(16, 14),
+        Instruction::LoadByte(..) => 6,
+        Instruction::AddByte(..) => 10,
+        Instruction::LoadReg(..)
+        | Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::AddReg(..)
+        | Instruction::Sub(..)
+        | Instruction::ShiftRight(..)
+        | Instruction::SubN(..)
+        | Instruction::ShiftLeft(..) => 44,
+        Instruction::LoadI(_) | Instruction::LoadILong => 12,
+        Instruction::Random(..) => 36,
+        // A SUPER-CHIP 16x16 sprite is charged for its 32 bytes.
+        Instruction::Draw(_, _, 0) => 68 + 16 * 32,
+        Instruction::Draw(_, _, n) => 68 + 16 * n as u32,
+        Instruction::LoadDelay(_)
+        | Instruction::WaitKey(_)
+        | Instruction::SetDelay(_)
+        | Instruction::SetSound(_) => 10,
+        Instruction::AddI(_) => 19,
+        Instruction::LoadFont(_) | Instruction::LoadBigFont(_) => 20,
+        Instruction::Bcd(_) => 204,
+        Instruction::StoreRegs(x)
+        | Instruction::LoadRegs(x)
+        | Instruction::StoreFlags(x)
+        | Instruction::LoadFlags(x) => registers(x + 1),
+        Instruction::StoreRange(x, y) | Instruction::LoadRange(x, y) => {
+            registers(x.max(y) - x.min(y) + 1)
+        }
+        Instruction::ScrollDown(_)
+        | Instruction::ScrollUp(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::Low
+        | Instruction::High => 24,
+        Instruction::Exit
+        | Instruction::Plane(_)
+        | Instruction::Audio
+        | Instruction::Pitch(_)
+        | Instruction::Unknown(_) => 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_cost_more_when_taken() {
+        let skip = Instruction::SkipEqByte(0, 0);
+        assert_eq!(vip_cycles(skip, true), 12);
+        assert_eq!(vip_cycles(skip, false), 10);
+    }
+
+    #[test]
+    fn sprites_cost_by_their_height() {
+        assert_eq!(vip_cycles(Instruction::Draw(0, 1, 1), false), 84);
+        assert_eq!(vip_cycles(Instruction::Draw(0, 1, 15), false), 308);
+        assert_eq!(vip_cycles(Instruction::Draw(0, 1, 0), false), 580);
+    }
+
+    #[test]
+    fn register_loads_and_stores_cost_by_their_count() {
+        assert_eq!(vip_cycles(Instruction::StoreRegs(0), false), 18);
+        assert_eq!(vip_cycles(Instruction::LoadRegs(0xF), false), 228);
+        assert_eq!(
+            vip_cycles(Instruction::StoreRange(2, 5), false),
+            vip_cycles(Instruction::LoadRange(5, 2), false)
+        );
+        assert_eq!(vip_cycles(Instruction::StoreRange(2, 5), false), 60);
+    }
+}